@@ -7,7 +7,10 @@ export type PlayableInfo = {
 export type SugarCubeLabel = {
   id: string;
   name: string | undefined;
+  description: string | undefined;
+  tags: string[];
   index: string;
   layers: string[];
   mods: [string, string][] | undefined;
+  resolvedMods: [string, string, string][] | undefined;
 };
@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Result, anyhow};
+
+use crate::{
+    element::sc::scan_all_layers,
+    util::config::{ManageType, ReadConfig, config_ref},
+};
+
+const USAGE: &str = "Usage: uni-server [COMMAND]
+
+Commands:
+  serve                   Start the server (default)
+  dedup-report [--top N]  Report files with identical content across layers";
+
+/// What to run, parsed from the command line
+pub enum Command {
+    Serve,
+    DedupReport { top: usize },
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        None | Some("serve") => Ok(Command::Serve),
+        Some("dedup-report") => {
+            let mut top = 20;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--top" => {
+                        top = args
+                            .next()
+                            .and_then(|n| n.parse().ok())
+                            .ok_or_else(|| anyhow!("--top requires a number\n\n{USAGE}"))?;
+                    }
+                    other => return Err(anyhow!("Unknown argument '{other}'\n\n{USAGE}")),
+                }
+            }
+            Ok(Command::DedupReport { top })
+        }
+        Some("-h" | "--help" | "help") => {
+            println!("{USAGE}");
+            std::process::exit(0);
+        }
+        Some(other) => Err(anyhow!("Unknown command '{other}'\n\n{USAGE}")),
+    }
+}
+
+/// Group the files of every SugarCube layer, shared or private to an
+/// instance directory, by content hash
+/// and print how much space duplicates take.
+pub fn dedup_report(top: usize) -> Result<()> {
+    let mut groups: HashMap<(u64, u64), Vec<(String, String)>> = HashMap::new();
+    let mut layer_count = 0;
+
+    let mut manages = config_ref().manage_iter().collect::<Vec<_>>();
+    manages.sort_by_key(|(id, _)| *id);
+    for (id, manage) in manages {
+        if !matches!(manage.mode, ManageType::SugarCube { .. }) {
+            continue;
+        }
+        for (label, layers) in scan_all_layers(id)? {
+            let mut layers = layers.into_iter().collect::<Vec<_>>();
+            layers.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (name, mfs) in layers {
+                layer_count += 1;
+                let layer = format!("{label}/{name}");
+                for (path, node) in mfs.iter() {
+                    let meta = node.meta();
+                    if let Some(hash) = meta.hash {
+                        groups
+                            .entry((hash, meta.size))
+                            .or_default()
+                            .push((layer.clone(), path.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    let (mut files, mut total) = (0u64, 0u64);
+    let mut unique = 0u64;
+    let mut duplicated = Vec::new();
+    // Shared files and bytes per pair of layers
+    let mut pairs: BTreeMap<(String, String), (u64, u64)> = BTreeMap::new();
+    for ((_, size), mut copies) in groups {
+        files += copies.len() as u64;
+        total += size * copies.len() as u64;
+        unique += size;
+        if copies.len() < 2 {
+            continue;
+        }
+        copies.sort();
+
+        let mut layers = copies.iter().map(|(l, _)| l.as_str()).collect::<Vec<_>>();
+        layers.dedup();
+        for (i, a) in layers.iter().enumerate() {
+            for b in &layers[i + 1..] {
+                let pair = pairs.entry((a.to_string(), b.to_string())).or_default();
+                pair.0 += 1;
+                pair.1 += size;
+            }
+        }
+        duplicated.push((size, copies));
+    }
+    let saving = total - unique;
+
+    println!(
+        "Scanned {} files ({}) in {} layers",
+        files,
+        human_size(total),
+        layer_count
+    );
+    println!(
+        "Duplicated: {} contents in {} files, {} could be saved",
+        duplicated.len(),
+        duplicated.iter().map(|(_, c)| c.len()).sum::<usize>(),
+        human_size(saving)
+    );
+
+    if !duplicated.is_empty() {
+        duplicated.sort_by(|(sa, ca), (sb, cb)| {
+            (sb * (cb.len() as u64 - 1))
+                .cmp(&(sa * (ca.len() as u64 - 1)))
+                .then_with(|| ca.cmp(cb))
+        });
+        println!("\nLargest savings:");
+        for (size, copies) in duplicated.iter().take(top) {
+            println!("  {} x{}", human_size(*size), copies.len());
+            for (layer, path) in copies {
+                println!("    {layer}:{path}");
+            }
+        }
+    }
+
+    if !pairs.is_empty() {
+        let mut pairs = pairs.into_iter().collect::<Vec<_>>();
+        pairs.sort_by(|(_, (_, a)), (_, (_, b))| b.cmp(a));
+        println!("\nLayers sharing content:");
+        for ((a, b), (count, bytes)) in pairs.iter().take(top) {
+            println!("  {a} <-> {b}: {count} files, {}", human_size(*bytes));
+        }
+    }
+
+    Ok(())
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
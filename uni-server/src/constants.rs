@@ -1,2 +1,13 @@
+use lazy_static::lazy_static;
+
+use crate::util::etag::etag_hash;
+
 pub const CACHE_HEADER: &str = "public, max-age=31536000";
 pub const SSI_MOD_ID: &str = "save-sync-integration";
+/// Largest mod zip accepted by the upload API
+pub const MOD_UPLOAD_LIMIT: usize = 256 * 1024 * 1024;
+pub const SSI_MOD_INTERNAL: &[u8] = include_bytes!("../../resources/save-sync-integration.mod.zip");
+
+lazy_static! {
+    pub static ref SSI_MOD_ETAG: String = etag_hash(SSI_MOD_INTERNAL);
+}
@@ -1,45 +1,66 @@
-use anyhow::{anyhow, Ok, Result};
-use sc::{create_sc_info, SugarCubeInfo};
-use std::{collections::HashMap, fs, path::PathBuf};
+use anyhow::{Ok, Result, anyhow};
+use sc::{SugarCubeInfo, create_sc_info};
+use std::{collections::HashMap, fs, path::PathBuf, sync::Arc};
 use tracing::{error, info, warn};
 
-use crate::util::config::{config_ref, ManageInfo, ManageType, ReadConfig};
+use crate::util::{
+    cache_policy::CachePolicy,
+    config::{ManageInfo, ManageType, ReadConfig, config_ref},
+};
 
+pub(crate) mod modloader;
 pub(crate) mod sc;
 
 #[derive(Debug, Default)]
 pub struct LoadedMapping {
-    map: HashMap<String, LoadedType>,
+    map: HashMap<String, Arc<LoadedType>>,
 }
 
 impl LoadedMapping {
-    pub fn get(&self, id: &str) -> Option<&LoadedType> {
+    pub fn get(&self, id: &str) -> Option<&Arc<LoadedType>> {
         self.map.get(id)
     }
 
     pub fn insert(&mut self, id: String, loaded_type: LoadedType) {
-        self.map.insert(id, loaded_type);
+        self.map.insert(id, Arc::new(loaded_type));
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&String, &LoadedType)> {
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Arc<LoadedType>)> {
         self.map.iter()
     }
 }
 
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum LoadedType {
     Plain {
         root_path: PathBuf,
         enter_path: PathBuf,
+        cache: CachePolicy,
         original_ref: &'static ManageInfo,
     },
     SugarCube {
         info: SugarCubeInfo,
+        cache: CachePolicy,
         original_ref: &'static ManageInfo,
     },
 }
 
+impl LoadedType {
+    pub fn cache_policy(&self) -> &CachePolicy {
+        match self {
+            LoadedType::Plain { cache, .. } | LoadedType::SugarCube { cache, .. } => cache,
+        }
+    }
+
+    pub fn original_ref(&self) -> &'static ManageInfo {
+        match self {
+            LoadedType::Plain { original_ref, .. } | LoadedType::SugarCube { original_ref, .. } => {
+                original_ref
+            }
+        }
+    }
+}
+
 pub fn load_data_dir() -> Result<LoadedMapping> {
     let config = config_ref();
     let mut mapping = LoadedMapping::default();
@@ -58,57 +79,70 @@ pub fn load_data_dir() -> Result<LoadedMapping> {
     }
 
     for (id, manage_info) in config.manage_iter() {
-        info!(
-            "Loading data dir for {}: {}",
+        mapping.insert(id.clone(), load_manage(id, manage_info)?);
+    }
+
+    Ok(mapping)
+}
+
+/// Load a single manage from its data dir, also used to reload it after changes
+pub fn load_manage(id: &str, manage_info: &'static ManageInfo) -> Result<LoadedType> {
+    info!(
+        "Loading data dir for {}: {}",
+        id,
+        manage_info.name.clone().unwrap_or("No name?".to_string())
+    );
+    let path = config_ref().data_dir().join(id);
+    if !path.exists() {
+        warn!(
+            "Data directory for {} does not exist, creating: {}",
             id,
-            manage_info.name.clone().unwrap_or("No name?".to_string())
+            path.display()
         );
-        let path = data_dir.join(id);
-        if !path.exists() {
-            warn!(
-                "Data directory for {} does not exist, creating: {}",
-                id,
-                path.display()
-            );
-            fs::create_dir_all(&path)?;
-        }
-
-        match &manage_info.mode {
-            ManageType::Plain { enter_path } => {
-                let actual_path = &path;
-                let loaded_type = LoadedType::Plain {
-                    root_path: actual_path.clone(),
-                    enter_path: actual_path.join(enter_path),
-                    original_ref: &manage_info,
-                };
+        fs::create_dir_all(&path)?;
+    }
 
-                mapping.insert(id.clone(), loaded_type);
-            }
+    match &manage_info.mode {
+        ManageType::Plain { enter_path } => {
+            let actual_path = &path;
+            Ok(LoadedType::Plain {
+                root_path: actual_path.clone(),
+                enter_path: actual_path.join(enter_path),
+                cache: CachePolicy::new(&manage_info.cache)?,
+                original_ref: manage_info,
+            })
+        }
 
-            ManageType::SugarCube {
-                use_mods,
-                use_save_sync,
-            } => {
-                if *use_save_sync {
-                    let save_path = path.join("save");
-                    if !save_path.exists() {
-                        info!(
-                            "Creating save directory for {}: {}",
-                            id,
-                            save_path.display()
-                        );
-                        fs::create_dir(path.join("save"))?;
-                    }
+        ManageType::SugarCube {
+            use_mods,
+            use_save_sync,
+            save_sync_position,
+            verify_mods,
+        } => {
+            if *use_save_sync {
+                let save_path = path.join("save");
+                if !save_path.exists() {
+                    info!(
+                        "Creating save directory for {}: {}",
+                        id,
+                        save_path.display()
+                    );
+                    fs::create_dir(path.join("save"))?;
                 }
-                let loaded_type = LoadedType::SugarCube {
-                    info: create_sc_info(id, manage_info.name.clone(), *use_mods, *use_save_sync)?,
-                    original_ref: &manage_info,
-                };
-
-                mapping.insert(id.clone(), loaded_type);
             }
+            Ok(LoadedType::SugarCube {
+                info: create_sc_info(
+                    id,
+                    manage_info.name.clone(),
+                    *use_mods,
+                    *use_save_sync,
+                    *save_sync_position,
+                    *verify_mods,
+                    &manage_info.lookup,
+                )?,
+                cache: CachePolicy::new(&manage_info.cache)?,
+                original_ref: manage_info,
+            })
         }
     }
-
-    Ok(mapping)
 }
@@ -0,0 +1,619 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    fmt::{self, Display},
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Result, anyhow};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+use zip::ZipArchive;
+
+use crate::util::{
+    bin_cache::{decode_cache, encode_cache},
+    config::VerifyMods,
+};
+
+const BOOT_JSON: &str = "boot.json";
+const MANIFEST_CACHE_KIND: &str = "mod-manifest";
+/// Bump when [ModManifest] or [CachedManifest] changes shape
+const MANIFEST_CACHE_VERSION: u16 = 3;
+
+/// `boot.json` of a ModLoader mod, unknown fields are ignored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModManifest {
+    pub name: String,
+    pub version: String,
+    /// Mods required by this one, with version ranges
+    #[serde(default)]
+    pub dependence_info: Vec<ModDependence>,
+    #[serde(default)]
+    pub addon_plugin: Vec<ModAddon>,
+    #[serde(default)]
+    pub script_file_list: Vec<String>,
+    #[serde(default, rename = "scriptFileList_inject_early")]
+    pub script_file_list_inject_early: Vec<String>,
+    #[serde(default, rename = "scriptFileList_earlyload")]
+    pub script_file_list_earlyload: Vec<String>,
+    #[serde(default, rename = "scriptFileList_preload")]
+    pub script_file_list_preload: Vec<String>,
+    #[serde(default)]
+    pub style_file_list: Vec<String>,
+    #[serde(default)]
+    pub twee_file_list: Vec<String>,
+    #[serde(default)]
+    pub img_file_list: Vec<String>,
+    #[serde(default)]
+    pub addition_file: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModDependence {
+    pub mod_name: String,
+    pub version: String,
+}
+
+/// Addon of another mod used by this one, which is also a dependency
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModAddon {
+    pub mod_name: String,
+    pub addon_name: String,
+    pub mod_version: String,
+}
+
+/// A mod zip in the repository
+#[derive(Debug, Clone)]
+pub struct ModEntry {
+    pub path: PathBuf,
+    /// [None] if `boot.json` is missing or invalid
+    pub manifest: Option<ModManifest>,
+    /// Why the mod is unavailable, [None] if it passed the checks
+    pub error: Option<String>,
+}
+
+/// Mod referenced by an instance
+#[derive(Debug, Clone)]
+pub struct ModRef {
+    pub id: String,
+    /// Version resolved from [ModRef::requested]
+    pub sub_id: String,
+    /// Version, range or `latest` as written in the instance config
+    pub requested: String,
+    pub path: PathBuf,
+    pub manifest: Option<ModManifest>,
+}
+
+impl Display for ModRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.manifest {
+            Some(manifest) => write!(f, "'{}' ({}:{})", manifest.name, self.id, self.sub_id),
+            None => write!(f, "{}:{}", self.id, self.sub_id),
+        }
+    }
+}
+
+/// Provided by the game and ModLoader rather than the repository
+const EXTERNAL_MODS: [&str; 2] = ["ModLoader", "GameVersion"];
+
+/// Sort mods so that dependencies load first, keeping the configured order
+/// where there is no constraint. On failure every problem found is returned.
+pub fn order_mods(mods: Vec<ModRef>) -> Result<Vec<ModRef>, Vec<String>> {
+    let mut problems = Vec::new();
+
+    let mut by_name: HashMap<&str, usize> = HashMap::new();
+    for (index, mod_ref) in mods.iter().enumerate() {
+        let Some(manifest) = &mod_ref.manifest else {
+            continue;
+        };
+        if let Some(&other) = by_name.get(manifest.name.as_str()) {
+            problems.push(format!(
+                "{} and {} are both '{}', versions {} and {}",
+                mods[other],
+                mod_ref,
+                manifest.name,
+                mods[other].manifest.as_ref().map_or("", |m| &m.version),
+                manifest.version
+            ));
+        } else {
+            by_name.insert(&manifest.name, index);
+        }
+    }
+
+    // Edges from each dependency to its dependents
+    let mut dependents = vec![Vec::new(); mods.len()];
+    let mut in_degree = vec![0usize; mods.len()];
+    for (index, mod_ref) in mods.iter().enumerate() {
+        let Some(manifest) = &mod_ref.manifest else {
+            continue;
+        };
+        let requirements = manifest
+            .dependence_info
+            .iter()
+            .map(|dep| (dep.mod_name.as_str(), dep.version.as_str()))
+            .chain(
+                manifest
+                    .addon_plugin
+                    .iter()
+                    .map(|addon| (addon.mod_name.as_str(), addon.mod_version.as_str())),
+            );
+        for (name, range) in requirements {
+            if EXTERNAL_MODS.contains(&name) || name == manifest.name {
+                continue;
+            }
+            let Some(&dep_index) = by_name.get(name) else {
+                // Without a manifest a mod can only be recognized by its id
+                let unreadable = mods
+                    .iter()
+                    .find(|m| m.manifest.is_none() && m.id.eq_ignore_ascii_case(name));
+                problems.push(match unreadable {
+                    Some(dep) => format!(
+                        "{mod_ref} requires '{name}' {range}, {dep} is present but has no readable manifest"
+                    ),
+                    None => format!(
+                        "{mod_ref} requires '{name}' {range}, which is not in the instance"
+                    ),
+                });
+                continue;
+            };
+            let provided = mods[dep_index].manifest.as_ref().map_or("", |m| &m.version);
+            match version_matches(range, provided) {
+                Some(false) => problems.push(format!(
+                    "{mod_ref} requires '{name}' {range}, but {} is version {provided}",
+                    mods[dep_index]
+                )),
+                Some(true) => {}
+                None => warn!(
+                    "Cannot compare version '{}' of '{}' with '{}' required by {}, assumed compatible",
+                    provided, name, range, mod_ref
+                ),
+            }
+            if !dependents[dep_index].contains(&index) {
+                dependents[dep_index].push(index);
+                in_degree[index] += 1;
+            }
+        }
+    }
+
+    // Kahn's algorithm, always taking the earliest configured mod that is ready
+    let mut ready = (0..mods.len())
+        .filter(|&i| in_degree[i] == 0)
+        .map(Reverse)
+        .collect::<BinaryHeap<_>>();
+    let mut order = Vec::with_capacity(mods.len());
+    while let Some(Reverse(index)) = ready.pop() {
+        order.push(index);
+        for &dependent in &dependents[index] {
+            in_degree[dependent] -= 1;
+            if in_degree[dependent] == 0 {
+                ready.push(Reverse(dependent));
+            }
+        }
+    }
+    if order.len() < mods.len() {
+        // Drop mods merely waiting on a cycle, so only its members are named
+        let mut stuck = (0..mods.len())
+            .filter(|&i| in_degree[i] > 0)
+            .collect::<Vec<_>>();
+        while let Some(pos) = stuck
+            .iter()
+            .position(|&i| !dependents[i].iter().any(|d| stuck.contains(d)))
+        {
+            stuck.remove(pos);
+        }
+        let cyclic = stuck
+            .into_iter()
+            .map(|i| mods[i].to_string())
+            .collect::<Vec<_>>();
+        problems.push(format!("Cyclic dependency among {}", cyclic.join(", ")));
+    }
+
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    let mut mods = mods.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(order
+        .into_iter()
+        .filter_map(|index| mods[index].take())
+        .collect())
+}
+
+/// Pick the version of a mod for a reference from an instance config.
+///
+/// An existing sub id is used as is, otherwise `latest` or a semver range
+/// selects the highest matching version among available ones. Versions are read from the zip
+/// name, falling back to the manifest.
+pub fn resolve_mod_version<'a>(
+    versions: &'a HashMap<String, ModEntry>,
+    requested: &str,
+) -> Option<(&'a String, &'a ModEntry)> {
+    if let Some(found) = versions.get_key_value(requested) {
+        return Some(found);
+    }
+
+    // Ranges never match pre-releases unless named, `latest` prefers stable
+    // versions and takes a pre-release only when there is nothing else
+    let latest = requested.trim().eq_ignore_ascii_case("latest");
+    let alternatives = if latest {
+        Vec::new()
+    } else {
+        requested
+            .split("||")
+            .map(parse_range)
+            .collect::<Option<Vec<_>>>()?
+    };
+
+    versions
+        .iter()
+        .filter(|(_, entry)| entry.error.is_none())
+        .filter_map(|(sub_id, entry)| Some((entry_version(sub_id, entry)?, sub_id, entry)))
+        .filter(|(version, _, _)| latest || alternatives.iter().any(|req| req.matches(version)))
+        // Ties between equal versions go to the smallest sub id
+        .max_by(|(a, a_id, _), (b, b_id, _)| {
+            (a.pre.is_empty(), a)
+                .cmp(&(b.pre.is_empty(), b))
+                .then_with(|| b_id.cmp(a_id))
+        })
+        .map(|(_, sub_id, entry)| (sub_id, entry))
+}
+
+fn entry_version(sub_id: &str, entry: &ModEntry) -> Option<Version> {
+    parse_version(sub_id).or_else(|| parse_version(&entry.manifest.as_ref()?.version))
+}
+
+/// Parse a mod version leniently, `v1.2` is read as `1.2.0`
+pub fn parse_version(version: &str) -> Option<Version> {
+    let version = version.trim().trim_start_matches(['v', 'V']);
+    if let Ok(parsed) = Version::parse(version) {
+        return Some(parsed);
+    }
+    let (core, rest) = match version.find(['-', '+']) {
+        Some(pos) => version.split_at(pos),
+        None => (version, ""),
+    };
+    let parts = core.split('.').count();
+    if parts >= 3 {
+        return None;
+    }
+    Version::parse(&format!("{core}{}{rest}", ".0".repeat(3 - parts))).ok()
+}
+
+/// Whether `version` satisfies an npm style `range`, as used by ModLoader.
+///
+/// Comparators are separated by spaces and alternatives by `||`,
+/// [None] if either side cannot be parsed.
+pub fn version_matches(range: &str, version: &str) -> Option<bool> {
+    let version = parse_version(version)?;
+    let mut matched = false;
+    for alternative in range.split("||") {
+        matched |= parse_range(alternative)?.matches(&version);
+    }
+    Some(matched)
+}
+
+fn parse_range(range: &str) -> Option<VersionReq> {
+    let mut comparators: Vec<String> = Vec::new();
+    let mut pending_op = String::new();
+    for token in range.split_whitespace() {
+        // Operators may be separated from their version, as in `>= 1.0`
+        if token.chars().all(|c| "<>=~^".contains(c)) {
+            pending_op.push_str(token);
+            continue;
+        }
+        let mut op = std::mem::take(&mut pending_op);
+        // A bare version is exact in npm, where semver would read it as a caret
+        if op.is_empty() && !token.starts_with(|c: char| "<>=~^*xX".contains(c)) {
+            op.push('=');
+        }
+        comparators.push(format!("{op}{token}"));
+    }
+    if comparators.is_empty() {
+        return Some(VersionReq::STAR);
+    }
+    VersionReq::parse(&comparators.join(", ")).ok()
+}
+
+/// Read and parse `boot.json` at the root of a mod zip
+pub fn read_manifest(path: &Path) -> Result<ModManifest> {
+    let mut zip = ZipArchive::new(fs::File::open(path)?)?;
+    let mut file = zip
+        .by_name(BOOT_JSON)
+        .map_err(|_| anyhow!("{BOOT_JSON} not found"))?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+
+    Ok(serde_json::from_str(
+        content.trim_start_matches('\u{feff}'),
+    )?)
+}
+
+/// Check the central directory and the CRC of every entry
+pub fn verify_zip(path: &Path) -> Result<()> {
+    let mut zip = ZipArchive::new(fs::File::open(path)?)?;
+    for index in 0..zip.len() {
+        let mut file = zip.by_index(index)?;
+        let name = file.name().to_string();
+        // The reader checks the CRC once the entry is read to the end
+        io::copy(&mut file, &mut io::sink()).map_err(|e| anyhow!("entry '{name}': {e}"))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ManifestCache {
+    entries: HashMap<String, CachedManifest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedManifest {
+    size: u64,
+    modified: SystemTime,
+    manifest: Option<ModManifest>,
+    error: Option<String>,
+}
+
+/// Manifests and integrity of mod zips, reused from `cache.bin`
+/// while the zip is unchanged
+pub struct ManifestStore {
+    path: PathBuf,
+    verify: VerifyMods,
+    old: ManifestCache,
+    new: ManifestCache,
+    dirty: bool,
+}
+
+impl ManifestStore {
+    pub fn open(path: PathBuf, verify: VerifyMods) -> Self {
+        let old = match fs::read(&path) {
+            Ok(data) => decode_cache(MANIFEST_CACHE_KIND, MANIFEST_CACHE_VERSION, &data)
+                .unwrap_or_else(|e| {
+                    warn!("Mod manifest cache {} rejected: {}", path.display(), e);
+                    ManifestCache::default()
+                }),
+            Err(_) => ManifestCache::default(),
+        };
+        Self {
+            path,
+            verify,
+            old,
+            new: ManifestCache::default(),
+            dirty: false,
+        }
+    }
+
+    pub fn load(&mut self, zip_path: PathBuf) -> ModEntry {
+        let key = zip_path.to_string_lossy().to_string();
+        let metadata = match fs::metadata(&zip_path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                return ModEntry {
+                    path: zip_path,
+                    manifest: None,
+                    error: Some(e.to_string()),
+                };
+            }
+        };
+        let size = metadata.len();
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+
+        let cached = match self.old.entries.remove(&key) {
+            Some(cached)
+                if cached.size == size
+                    && cached.modified == modified
+                    && self.verify != VerifyMods::Always =>
+            {
+                if let Some(error) = &cached.error {
+                    warn!("Mod {} is broken (cached): {}", zip_path.display(), error);
+                }
+                cached
+            }
+            _ => {
+                self.dirty = true;
+                let checked = match self.verify {
+                    VerifyMods::Off => Ok(()),
+                    VerifyMods::Cached | VerifyMods::Always => verify_zip(&zip_path),
+                };
+                // Only a damaged zip marks the mod broken, a bad manifest just loses metadata
+                let (manifest, error) = match checked {
+                    Ok(()) => match read_manifest(&zip_path) {
+                        Ok(manifest) => (Some(manifest), None),
+                        Err(e) => {
+                            warn!("Mod {} has no usable manifest: {}", zip_path.display(), e);
+                            (None, None)
+                        }
+                    },
+                    Err(e) => {
+                        warn!("Mod {} is broken: {}", zip_path.display(), e);
+                        (None, Some(e.to_string()))
+                    }
+                };
+                CachedManifest {
+                    size,
+                    modified,
+                    manifest,
+                    error,
+                }
+            }
+        };
+        let entry = ModEntry {
+            path: zip_path,
+            manifest: cached.manifest.clone(),
+            error: cached.error.clone(),
+        };
+        self.new.entries.insert(key, cached);
+        entry
+    }
+
+    /// Write back when anything was parsed or removed
+    pub fn save(self) {
+        if !self.dirty && self.old.entries.is_empty() {
+            return;
+        }
+        match encode_cache(MANIFEST_CACHE_KIND, MANIFEST_CACHE_VERSION, &self.new) {
+            Ok(content) => {
+                if let Err(e) = fs::write(&self.path, content) {
+                    error!(
+                        "Error writing mod manifest cache to {}: {}",
+                        self.path.display(),
+                        e
+                    );
+                }
+            }
+            Err(e) => error!("Error encoding mod manifest cache: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let manifest: ModManifest = serde_json::from_str(
+            r#"{
+                "name": "ExampleMod",
+                "version": "1.2.0",
+                "styleFileList": ["style.css"],
+                "scriptFileList_inject_early": ["early.js"],
+                "dependenceInfo": [{ "modName": "ModLoader", "version": ">=2.0.0" }],
+                "addonPlugin": [{
+                    "modName": "TweeReplacer",
+                    "addonName": "TweeReplacerAddon",
+                    "modVersion": "1.0.0",
+                    "params": []
+                }],
+                "ignored": true
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.name, "ExampleMod");
+        assert_eq!(manifest.script_file_list_inject_early, ["early.js"]);
+        assert_eq!(manifest.dependence_info[0].mod_name, "ModLoader");
+        assert_eq!(manifest.addon_plugin[0].addon_name, "TweeReplacerAddon");
+        assert!(manifest.script_file_list.is_empty());
+    }
+
+    #[test]
+    fn test_version_matches() {
+        assert_eq!(version_matches("^2.17.1", "2.18.0"), Some(true));
+        assert_eq!(version_matches("^2.17.1", "3.0.0"), Some(false));
+        assert_eq!(version_matches(">= 1.0 < 2", "1.5"), Some(true));
+        assert_eq!(version_matches("1.x || ^3", "3.1.0"), Some(true));
+        assert_eq!(version_matches("*", "0.0.1"), Some(true));
+        assert_eq!(version_matches("1.0.0", "1.5.0"), Some(false));
+        assert_eq!(version_matches("1.0.0", "1.0.0"), Some(true));
+        assert_eq!(version_matches("1.x", "1.5.0"), Some(true));
+        assert_eq!(version_matches("^1.0", "not a version"), None);
+    }
+
+    #[test]
+    fn test_order_mods() {
+        let mod_ref = |name: &str, version: &str, deps: &[(&str, &str)]| ModRef {
+            id: name.to_lowercase(),
+            sub_id: version.to_string(),
+            requested: version.to_string(),
+            path: PathBuf::new(),
+            manifest: Some(ModManifest {
+                name: name.to_string(),
+                version: version.to_string(),
+                dependence_info: deps
+                    .iter()
+                    .map(|(name, version)| ModDependence {
+                        mod_name: name.to_string(),
+                        version: version.to_string(),
+                    })
+                    .collect(),
+                ..serde_json::from_str(r#"{"name": "", "version": ""}"#).unwrap()
+            }),
+        };
+        let names = |mods: Vec<ModRef>| mods.into_iter().map(|m| m.id).collect::<Vec<_>>();
+
+        let ordered = order_mods(vec![
+            mod_ref("C", "1.0.0", &[("B", "^1.0.0")]),
+            mod_ref("A", "1.0.0", &[("ModLoader", "^2.0.0")]),
+            mod_ref("B", "1.2.0", &[("A", ">=1.0.0")]),
+            mod_ref("D", "1.0.0", &[]),
+        ])
+        .unwrap();
+        assert_eq!(names(ordered), ["a", "b", "c", "d"]);
+
+        let problems = order_mods(vec![
+            mod_ref("A", "1.0.0", &[("B", "^1.0.0")]),
+            mod_ref("B", "1.0.0", &[("A", "^1.0.0")]),
+            mod_ref("C", "1.0.0", &[("A", "^2.0.0"), ("E", "*")]),
+        ])
+        .unwrap_err();
+        assert_eq!(problems.len(), 3, "{problems:?}");
+        assert!(problems[0].contains("but 'A' (a:1.0.0) is version 1.0.0"));
+        assert!(problems[1].contains("'E' *, which is not in the instance"));
+        assert_eq!(
+            problems[2],
+            "Cyclic dependency among 'A' (a:1.0.0), 'B' (b:1.0.0)"
+        );
+
+        let mut broken = mod_ref("B", "1.0.0", &[]);
+        broken.manifest = None;
+        let problems =
+            order_mods(vec![mod_ref("A", "1.0.0", &[("B", "^1.0.0")]), broken]).unwrap_err();
+        assert_eq!(
+            problems,
+            ["'A' (a:1.0.0) requires 'B' ^1.0.0, b:1.0.0 is present but has no readable manifest"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_mod_version() {
+        let versions = ["1.0", "1.2.3", "1.9.0", "1.10.0", "2.0.0-beta", "legacy"]
+            .into_iter()
+            .map(|sub_id| {
+                (
+                    sub_id.to_string(),
+                    ModEntry {
+                        path: PathBuf::new(),
+                        manifest: None,
+                        error: (sub_id == "1.10.0").then(|| "broken".to_string()),
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let resolve = |requested: &str| {
+            resolve_mod_version(&versions, requested).map(|(sub_id, _)| sub_id.as_str())
+        };
+
+        assert_eq!(resolve("legacy"), Some("legacy"));
+        assert_eq!(resolve("1.0"), Some("1.0"));
+        // 1.10.0 is broken, so the highest available one is picked
+        assert_eq!(resolve("^1.2"), Some("1.9.0"));
+        assert_eq!(resolve("~1.2"), Some("1.2.3"));
+        // The pre-release is higher but a stable version exists
+        assert_eq!(resolve("latest"), Some("1.9.0"));
+        assert_eq!(resolve("^3"), None);
+
+        let pre_releases = ["2.0.0-alpha", "2.0.0-beta"]
+            .into_iter()
+            .map(|sub_id| {
+                (
+                    sub_id.to_string(),
+                    ModEntry {
+                        path: PathBuf::new(),
+                        manifest: None,
+                        error: None,
+                    },
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            resolve_mod_version(&pre_releases, "latest").map(|(sub_id, _)| sub_id.as_str()),
+            Some("2.0.0-beta")
+        );
+    }
+}
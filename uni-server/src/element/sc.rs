@@ -1,33 +1,52 @@
 use std::{
-    collections::HashMap,
-    fs,
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs, io,
     path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::{Instant, SystemTime},
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use bincode::config::{standard, Configuration};
 use serde::{Deserialize, Serialize};
 use tracing::{error, info, warn};
 use walkdir::WalkDir;
+use xxhash_rust::xxh3::Xxh3;
 
+use super::modloader::{
+    ManifestStore, ModEntry, ModRef, order_mods, read_manifest, resolve_mod_version, verify_zip,
+};
 use crate::{
-    constants::SSI_MOD_ID,
+    constants::{SSI_MOD_ETAG, SSI_MOD_ID},
     util::{
-        config::{config_ref, Config, ReadConfig},
+        bin_cache::{decode_cache, encode_cache},
+        cache_policy::version_token,
+        config::{Config, LookupConfig, ReadConfig, SaveSyncPosition, VerifyMods, config_ref},
+        etag::etag_meta,
         mfs::MapFileSystem,
         path_ext::PathHelper,
     },
 };
 
-const INSTANCE_DIR_NAME: &'static str = "instance";
-const INDEX_DIR_NAME: &'static str = "index";
-const LAYER_DIR_NAME: &'static str = "layer";
-const MOD_DIR_NAME: &'static str = "mod";
+const INSTANCE_DIR_NAME: &str = "instance";
+const INDEX_DIR_NAME: &str = "index";
+const LAYER_DIR_NAME: &str = "layer";
+const MOD_DIR_NAME: &str = "mod";
+/// Marks a directory in `instance/` as an instance directory, see [InstanceDir]
+const INSTANCE_DIR_CONFIG: &str = "instance.toml";
+/// Instance ids that would overlap the `/api/sc/{manage_id}/...` routes
+const RESERVED_INSTANCE_IDS: [&str; 2] = ["instances", "mods"];
+
+const LAYER_CACHE_KIND: &str = "layer";
+/// Bump when [LayerCache] or [MapFileSystem] changes shape
+const LAYER_CACHE_VERSION: u16 = 3;
 
 trait ReadConfigSugarCube: ReadConfig {
     fn instance_dir(&self, id: &str) -> PathBuf {
@@ -49,34 +68,85 @@ impl ReadConfigSugarCube for Config {}
 type InstanceMap = HashMap<String, SugarCubeInstance>;
 type IndexMap = HashMap<String, PathBuf>;
 type LayerMap = HashMap<String, MapFileSystem>;
-type ModMap = HashMap<String, HashMap<String, PathBuf>>;
-type ModRefMap = HashMap<(String, String), PathBuf>;
+type ModMap = HashMap<String, HashMap<String, ModEntry>>;
 
 #[derive(Debug)]
 pub struct SugarCubeInfo {
     pub name: Option<String>,
     pub instances: InstanceMap,
+    pub indexes: IndexMap,
+    pub layers: LayerMap,
     pub mods: ModMap,
 
     pub use_mods: bool,
     pub use_save_sync_mod: bool,
+    pub save_sync_position: SaveSyncPosition,
 }
 
 impl SugarCubeInfo {
     pub fn get_instance(&self, id: &str) -> Option<&SugarCubeInstance> {
         self.instances.get(id)
     }
-    pub fn get_mod(&self, mod_id: &str, mod_sub_id: &str) -> Option<&PathBuf> {
+    /// Like [Self::get_instance], but disabled instances are not found
+    pub fn get_playable(&self, id: &str) -> Option<&SugarCubeInstance> {
+        self.get_instance(id).filter(|i| i.enabled)
+    }
+    pub fn get_mod(&self, mod_id: &str, mod_sub_id: &str) -> Option<&ModEntry> {
         self.mods.get(mod_id).and_then(|m| m.get(mod_sub_id))
     }
 
+    /// Every layer of an instance providing `path`, from the highest precedence,
+    /// along with the layer whose whiteout hides it if any
+    #[allow(clippy::result_large_err)]
+    pub fn resolve_path(&self, instance_id: &str, path: &str) -> Result<PathResolution, Response> {
+        let instance = self.get_instance(instance_id).ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("Instance ID {instance_id} not found"),
+            )
+                .into_response()
+        })?;
+
+        // Candidates are looked up by the entry the normalized lookup settles on
+        let path = instance.layer_merged.resolve_key(path).unwrap_or(path);
+        let mut candidates: Vec<LayerCandidate> = Vec::new();
+        for layer_id in instance.layers.iter() {
+            let private = instance.dir.as_deref().map(|d| &d.layers);
+            let Some(mfs) = scoped(private, &self.layers, layer_id) else {
+                continue;
+            };
+            if mfs.hides(path) {
+                for candidate in candidates.iter_mut().filter(|c| c.hidden_by.is_none()) {
+                    candidate.hidden_by = Some(layer_id.clone());
+                }
+            }
+            if let Some(node) = mfs.get(path) {
+                let meta = node.meta();
+                candidates.push(LayerCandidate {
+                    layer: layer_id.clone(),
+                    size: meta.size,
+                    etag: meta.etag.clone(),
+                    hidden_by: None,
+                });
+            }
+        }
+        candidates.reverse();
+
+        Ok(PathResolution {
+            path: path.to_string(),
+            winner: instance.layer_merged.origin(path).map(str::to_string),
+            candidates,
+        })
+    }
+
     pub fn check_instance(&self, id: &str) -> Option<Response> {
-        if self.instances.contains_key(id) {
+        if self.get_playable(id).is_some() {
             None
         } else {
             Some((StatusCode::NOT_FOUND, format!("Instance ID {id} not found")).into_response())
         }
     }
+    #[allow(clippy::result_large_err)]
     pub fn generate_mod_list(
         &self,
         instance_id: &str,
@@ -88,25 +158,534 @@ impl SugarCubeInfo {
                 (StatusCode::BAD_REQUEST, "Mod list generation is disabled").into_response()
             );
         }
-        let instance = self.get_instance(instance_id).ok_or_else(|| {
+        let instance = self.get_playable(instance_id).ok_or_else(|| {
             (
                 StatusCode::NOT_FOUND,
                 format!("Instance ID {instance_id} not found"),
             )
                 .into_response()
         })?;
+        // Version tokens make the URLs content-hashed, so they can be cached as immutable
+        let ssi_url = format!(
+            "/repo/sc/mod/{manage_id}/{SSI_MOD_ID}/0?v={}",
+            version_token(&SSI_MOD_ETAG)
+        );
         let mut mod_list = instance
             .mods_ref
-            .keys()
-            .map(|(mod_id, mod_sub_id)| format!("/repo/sc/mod/{manage_id}/{mod_id}/{mod_sub_id}"))
+            .iter()
+            .map(|mod_ref| {
+                if mod_ref.id == SSI_MOD_ID {
+                    return ssi_url.clone();
+                }
+                let url = format!("/repo/sc/mod/{manage_id}/{}/{}", mod_ref.id, mod_ref.sub_id);
+                match fs::metadata(&mod_ref.path) {
+                    Ok(metadata) => format!("{url}?v={}", version_token(&etag_meta(&metadata))),
+                    Err(_) => url,
+                }
+            })
             .collect::<Vec<_>>();
 
-        if self.use_save_sync_mod {
-            mod_list.push(format!("/repo/sc/mod/{manage_id}/{SSI_MOD_ID}/0").to_string());
+        let explicit = instance.mods_ref.iter().any(|m| m.id == SSI_MOD_ID);
+        if self.use_save_sync_mod && !explicit {
+            match self.save_sync_position {
+                SaveSyncPosition::First => mod_list.insert(0, ssi_url),
+                SaveSyncPosition::Last => mod_list.push(ssi_url),
+            }
         }
 
         Ok(mod_list)
     }
+
+    /// Resolve and order the mods of an instance as loading would,
+    /// failing on anything that would be skipped
+    pub fn check_mods(
+        &self,
+        instance_id: &str,
+        mods: &[(String, String)],
+    ) -> Result<Vec<ModRef>, Vec<String>> {
+        let (mod_refs, mut problems) =
+            collect_mod_refs(instance_id, mods, &self.mods, self.use_save_sync_mod);
+        match order_mods(mod_refs) {
+            Ok(mod_refs) if problems.is_empty() => Ok(mod_refs),
+            Ok(_) => Err(problems),
+            Err(order_problems) => {
+                problems.extend(order_problems);
+                Err(problems)
+            }
+        }
+    }
+
+    /// Store an uploaded mod zip as `{mod_id}/{version}.zip`, both read from its
+    /// `boot.json` unless given. Returns the id and version it was stored as.
+    #[allow(clippy::result_large_err)]
+    pub fn install_mod(
+        &self,
+        manage_id: &str,
+        data: &[u8],
+        mod_id: Option<&str>,
+        version: Option<&str>,
+        overwrite: bool,
+    ) -> Result<(String, String), Response> {
+        static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        if !self.use_mods {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Game ID {manage_id} does not use mods"),
+            )
+                .into_response());
+        }
+        let mod_dir = config_ref().mod_dir(manage_id);
+        let upload_path = mod_dir.join(format!(
+            ".upload-{}.tmp",
+            UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let internal = |err: io::Error| {
+            error!("Failed to store uploaded mod for {manage_id}: {err}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to store mod: {err}"),
+            )
+                .into_response()
+        };
+
+        let result = (|| {
+            fs::create_dir_all(&mod_dir).map_err(internal)?;
+            fs::write(&upload_path, data).map_err(internal)?;
+
+            let invalid = |err: anyhow::Error| {
+                (StatusCode::BAD_REQUEST, format!("Invalid mod zip: {err}")).into_response()
+            };
+            verify_zip(&upload_path).map_err(invalid)?;
+            let manifest = read_manifest(&upload_path).map_err(invalid)?;
+
+            let derive = |value: &str, param: &str| {
+                sanitize_file_name(value).ok_or_else(|| {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        format!("Cannot derive a file name from '{value}', pass ?{param}="),
+                    )
+                        .into_response()
+                })
+            };
+            let mod_id = match mod_id {
+                Some(id) => checked_file_name(id)?,
+                None => derive(&manifest.name, "id")?,
+            };
+            let version = match version {
+                Some(version) => checked_file_name(version)?,
+                None => derive(&manifest.version, "version")?,
+            };
+            if mod_id == SSI_MOD_ID {
+                return Err((
+                    StatusCode::CONFLICT,
+                    format!("Mod ID {SSI_MOD_ID} is built in"),
+                )
+                    .into_response());
+            }
+
+            let target = mod_dir.join(&mod_id).join(format!("{version}.zip"));
+            if target.exists() && !overwrite {
+                return Err((
+                    StatusCode::CONFLICT,
+                    format!("Mod {mod_id}:{version} already exists"),
+                )
+                    .into_response());
+            }
+            fs::create_dir_all(mod_dir.join(&mod_id)).map_err(internal)?;
+            fs::rename(&upload_path, &target).map_err(internal)?;
+
+            info!(
+                "Installed mod '{}' as {}:{} for {}",
+                manifest.name, mod_id, version, manage_id
+            );
+            Ok((mod_id, version))
+        })();
+
+        if upload_path.exists() {
+            let _ = fs::remove_file(&upload_path);
+        }
+        result
+    }
+
+    /// Delete a stored mod version, refused while an instance loads it unless forced
+    #[allow(clippy::result_large_err)]
+    pub fn remove_mod(&self, mod_id: &str, mod_sub_id: &str, force: bool) -> Result<(), Response> {
+        let entry = self.get_mod(mod_id, mod_sub_id).ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("Mod ID {mod_id}:{mod_sub_id} not found"),
+            )
+                .into_response()
+        })?;
+
+        let mut users = self
+            .instances
+            .values()
+            .filter(|i| {
+                i.mods_ref
+                    .iter()
+                    .any(|m| m.id == mod_id && m.sub_id == mod_sub_id)
+            })
+            .map(|i| i.id.as_str())
+            .collect::<Vec<_>>();
+        if !users.is_empty() && !force {
+            users.sort();
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Mod {mod_id}:{mod_sub_id} is used by {}", users.join(", ")),
+            )
+                .into_response());
+        }
+
+        if let Err(err) = fs::remove_file(&entry.path) {
+            error!("Failed to remove mod {}: {err}", entry.path.display());
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to remove mod: {err}"),
+            )
+                .into_response());
+        }
+        // Drop the mod directory along with its last version
+        if let Some(dir) = entry.path.parent()
+            && dir.read_dir().is_ok_and(|mut d| d.next().is_none())
+        {
+            let _ = fs::remove_dir(dir);
+        }
+        info!("Removed mod {}:{}", mod_id, mod_sub_id);
+        Ok(())
+    }
+
+    /// Add a mod to an instance, or change the version it requests,
+    /// and save the instance config
+    #[allow(clippy::result_large_err)]
+    pub fn attach_mod(
+        &self,
+        instance_id: &str,
+        mod_id: &str,
+        requested: &str,
+    ) -> Result<SugarCubeInstanceConfig, Response> {
+        let instance = self.instance_or_404(instance_id)?;
+        let mut config = instance.original_conf.clone();
+        config
+            .mods
+            .insert((mod_id.to_string(), requested.to_string()));
+        self.save_instance(&instance.source, instance.dir.as_deref(), config)
+    }
+
+    /// Remove every reference to a mod from an instance and save the instance config
+    #[allow(clippy::result_large_err)]
+    pub fn detach_mod(
+        &self,
+        instance_id: &str,
+        mod_id: &str,
+    ) -> Result<SugarCubeInstanceConfig, Response> {
+        let instance = self.instance_or_404(instance_id)?;
+        if !instance.mods.iter().any(|(id, _)| id == mod_id) {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("Mod {mod_id} is not attached to {instance_id}"),
+            )
+                .into_response());
+        }
+        let mut config = instance.original_conf.clone();
+        config.mods.remove(mod_id);
+        self.save_instance(&instance.source, instance.dir.as_deref(), config)
+    }
+
+    /// Every reason a config would not load as an instance, empty if none,
+    /// including instances that extend it and would break with it.
+    /// `dir` is the instance directory the config is saved in, if any.
+    pub fn check_instance_config(
+        &self,
+        config: &SugarCubeInstanceConfig,
+        dir: Option<&InstanceDir>,
+    ) -> Vec<String> {
+        let mut problems = Vec::new();
+        // Ids that already loaded stay valid, only new ones must be usable
+        if !self.instances.contains_key(&config.id) {
+            if !is_file_name(&config.id) {
+                problems.push(format!("'{}' is not a valid instance id", config.id));
+            }
+            if RESERVED_INSTANCE_IDS.contains(&config.id.as_str()) {
+                problems.push(format!("'{}' is a reserved instance id", config.id));
+            }
+        }
+
+        let mut configs = self
+            .instances
+            .values()
+            .map(|i| (i.id.as_str(), &i.original_conf))
+            .collect::<HashMap<_, _>>();
+        configs.insert(config.id.as_str(), config);
+        match resolve_extends(&config.id, &configs) {
+            Ok(resolved) => problems.extend(self.check_resolved(&config.id, &resolved, dir)),
+            Err(problem) => {
+                problems.push(problem);
+                return problems;
+            }
+        }
+
+        let mut dependents = self
+            .instances
+            .values()
+            .filter(|i| i.id != config.id && extends_from(&i.id, &config.id, &configs))
+            .collect::<Vec<_>>();
+        dependents.sort_by(|a, b| a.id.cmp(&b.id));
+        for dependent in dependents {
+            let dependent_problems = match resolve_extends(&dependent.id, &configs) {
+                Ok(resolved) => {
+                    self.check_resolved(&dependent.id, &resolved, dependent.dir.as_deref())
+                }
+                Err(problem) => vec![problem],
+            };
+            problems.extend(
+                dependent_problems
+                    .into_iter()
+                    .map(|problem| format!("{} (extends {}): {problem}", dependent.id, config.id)),
+            );
+        }
+        problems
+    }
+
+    /// Check that what a resolved config refers to exists
+    fn check_resolved(
+        &self,
+        instance_id: &str,
+        resolved: &ResolvedConfig,
+        dir: Option<&InstanceDir>,
+    ) -> Vec<String> {
+        let mut problems = Vec::new();
+        if scoped(dir.map(|d| &d.indexes), &self.indexes, &resolved.index).is_none() {
+            problems.push(format!("Index {} not found", resolved.index));
+        }
+        for layer_id in resolved.layers.iter() {
+            if scoped(dir.map(|d| &d.layers), &self.layers, layer_id).is_none() {
+                problems.push(format!("Layer {layer_id} not found"));
+            }
+        }
+        if self.use_mods
+            && let Err(mod_problems) = self.check_mods(instance_id, &resolved.mods)
+        {
+            problems.extend(mod_problems);
+        }
+        problems
+    }
+
+    /// Add an instance from a config, saved as `{id}.{format}` in the instance directory
+    #[allow(clippy::result_large_err)]
+    pub fn create_instance(
+        &self,
+        manage_id: &str,
+        config: SugarCubeInstanceConfig,
+        format: &str,
+    ) -> Result<SugarCubeInstanceConfig, Response> {
+        if !matches!(format, "json" | "toml" | "yaml" | "yml") {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Unsupported instance config format: {format}"),
+            )
+                .into_response());
+        }
+        let path = config_ref()
+            .instance_dir(manage_id)
+            .join(format!("{}.{format}", config.id));
+        self.check_new_instance(&config.id, Some(&path))?;
+        self.save_instance(&path, None, config)
+    }
+
+    /// Replace the config of an instance, keeping the file and its format
+    #[allow(clippy::result_large_err)]
+    pub fn update_instance(
+        &self,
+        instance_id: &str,
+        config: SugarCubeInstanceConfig,
+    ) -> Result<SugarCubeInstanceConfig, Response> {
+        let instance = self.instance_or_404(instance_id)?;
+        if config.id != instance_id {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Instance ID {} does not match {instance_id}, clone it to rename",
+                    config.id
+                ),
+            )
+                .into_response());
+        }
+        self.save_instance(&instance.source, instance.dir.as_deref(), config)
+    }
+
+    /// Copy an instance under a new id. The copy is added to the file of the
+    /// original if that holds several instances or is in an instance directory,
+    /// otherwise it gets a file of the same format next to it.
+    #[allow(clippy::result_large_err)]
+    pub fn clone_instance(
+        &self,
+        instance_id: &str,
+        new_id: &str,
+        name: Option<String>,
+    ) -> Result<SugarCubeInstanceConfig, Response> {
+        let instance = self.instance_or_404(instance_id)?;
+        let shared_file = instance.dir.is_some()
+            || !matches!(
+                read_instance_file(&instance.source),
+                Ok(InstanceFile::One(_))
+            );
+        let path = if shared_file {
+            instance.source.clone()
+        } else {
+            let ext = config_format(&instance.source);
+            instance.source.with_file_name(format!("{new_id}.{ext}"))
+        };
+        self.check_new_instance(new_id, (!shared_file).then_some(path.as_path()))?;
+
+        let config = SugarCubeInstanceConfig {
+            id: new_id.to_string(),
+            name: name.or_else(|| instance.original_conf.name.clone()),
+            ..instance.original_conf.clone()
+        };
+        let dir = if shared_file {
+            instance.dir.as_deref()
+        } else {
+            None
+        };
+        self.save_instance(&path, dir, config)
+    }
+
+    /// Delete an instance from its config file, its saves are kept.
+    /// Refused while other instances extend it.
+    #[allow(clippy::result_large_err)]
+    pub fn delete_instance(&self, instance_id: &str) -> Result<(), Response> {
+        let instance = self.instance_or_404(instance_id)?;
+        let mut children = self
+            .instances
+            .values()
+            .filter(|i| i.original_conf.extends.as_deref() == Some(instance_id))
+            .map(|i| i.id.as_str())
+            .collect::<Vec<_>>();
+        if !children.is_empty() {
+            children.sort();
+            return Err((
+                StatusCode::CONFLICT,
+                format!(
+                    "Instance {instance_id} is extended by {}",
+                    children.join(", ")
+                ),
+            )
+                .into_response());
+        }
+        if let Err(err) = remove_instance_config(&instance.source, instance_id) {
+            error!(
+                "Failed to remove instance config {}: {err}",
+                instance.source.display()
+            );
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to remove instance config: {err}"),
+            )
+                .into_response());
+        }
+        info!("Removed instance {}", instance_id);
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn instance_or_404(&self, instance_id: &str) -> Result<&SugarCubeInstance, Response> {
+        self.get_instance(instance_id).ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("Instance ID {instance_id} not found"),
+            )
+                .into_response()
+        })
+    }
+
+    /// `path` is the file about to be created for it, if any
+    #[allow(clippy::result_large_err)]
+    fn check_new_instance(&self, instance_id: &str, path: Option<&Path>) -> Result<(), Response> {
+        if self.instances.contains_key(instance_id) || path.is_some_and(Path::exists) {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("Instance ID {instance_id} already exists"),
+            )
+                .into_response());
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn save_instance(
+        &self,
+        path: &Path,
+        dir: Option<&InstanceDir>,
+        config: SugarCubeInstanceConfig,
+    ) -> Result<SugarCubeInstanceConfig, Response> {
+        let problems = self.check_instance_config(&config, dir);
+        if !problems.is_empty() {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "Instance {} would be invalid:\n  - {}",
+                    config.id,
+                    problems.join("\n  - ")
+                ),
+            )
+                .into_response());
+        }
+        if let Err(err) = save_instance_config(path, &config) {
+            error!("Failed to save instance config {}: {err}", path.display());
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to save instance config: {err}"),
+            )
+                .into_response());
+        }
+        Ok(config)
+    }
+}
+
+fn is_file_name(name: &str) -> bool {
+    sanitize_file_name(name).as_deref() == Some(name)
+}
+
+/// Accept a client given id or version only if it is usable as a file name as is
+#[allow(clippy::result_large_err)]
+fn checked_file_name(name: &str) -> Result<String, Response> {
+    if is_file_name(name) {
+        Ok(name.to_string())
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            format!("'{name}' is not a valid mod id or version"),
+        )
+            .into_response())
+    }
+}
+
+/// Keep ASCII alphanumerics, `.`, `-` and `_`, replacing anything else with `_`,
+/// [None] when most of the name would be replaced, as with CJK names that
+/// would all collapse into the same id
+fn sanitize_file_name(name: &str) -> Option<String> {
+    let mut replaced = 0;
+    let name = name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                replaced += 1;
+                '_'
+            }
+        })
+        .collect::<String>();
+    // Never hidden or a parent reference
+    let name = name.trim_start_matches('.');
+    if name.is_empty() || replaced * 2 > name.chars().count() {
+        None
+    } else {
+        Some(name.to_string())
+    }
 }
 
 #[derive(Debug)]
@@ -114,25 +693,385 @@ impl SugarCubeInfo {
 pub struct SugarCubeInstance {
     pub id: String,
     pub name: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub enabled: bool,
+    pub hidden: bool,
+    /// Effective values, with `extends` applied
+    pub index: String,
+    pub layers: Vec<String>,
+    pub mods: Vec<(String, String)>,
     pub index_path: PathBuf,
     pub layer_merged: MapFileSystem,
-    pub mods_ref: ModRefMap,
+    /// In load order, dependencies first
+    pub mods_ref: Vec<ModRef>,
     pub original_conf: SugarCubeInstanceConfig,
+    /// File the config was read from
+    pub source: PathBuf,
+    /// Set if defined in an instance directory
+    pub dir: Option<Arc<InstanceDir>>,
+}
+
+/// Directory in `instance/` with an `instance.toml`, which may hold `index/`
+/// and `layer/` folders of its own. Their entries are only visible to the
+/// instances defined there and shadow shared ones with the same id.
+#[derive(Debug)]
+pub struct InstanceDir {
+    pub indexes: IndexMap,
+    pub layers: LayerMap,
+}
+
+fn load_instance_dir(id: &str, root: &Path) -> Result<InstanceDir> {
+    let label = format!(
+        "{id}/{}",
+        root.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let index_dir = root.join(INDEX_DIR_NAME);
+    let layer_dir = root.join(LAYER_DIR_NAME);
+    Ok(InstanceDir {
+        indexes: if index_dir.is_dir() {
+            scan_indexes(&label, &index_dir)?
+        } else {
+            HashMap::new()
+        },
+        layers: if layer_dir.is_dir() {
+            scan_layers(&label, &layer_dir, true)?
+        } else {
+            HashMap::new()
+        },
+    })
+}
+
+/// Look up an index or layer, private entries of an [InstanceDir] first
+fn scoped<'a, T>(
+    private: Option<&'a HashMap<String, T>>,
+    shared: &'a HashMap<String, T>,
+    id: &str,
+) -> Option<&'a T> {
+    private.and_then(|p| p.get(id)).or_else(|| shared.get(id))
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SugarCubeInstanceConfig {
     pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Instance to inherit from, see [resolve_extends]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Required unless inherited
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub index: String,
+    #[serde(default, skip_serializing_if = "ListSpec::is_unchanged")]
+    pub layers: ListSpec<String>,
+    #[serde(default, skip_serializing_if = "ListSpec::is_unchanged")]
+    pub mods: ListSpec<(String, String)>,
+    /// Disabled instances are loaded but neither listed nor playable,
+    /// they can still be extended
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// Not listed, but playable by URL
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+/// List in an instance config, either given in full
+/// or as changes to the list inherited through `extends`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, from = "ListSpecDe<T>")]
+pub enum ListSpec<T> {
+    Set(Vec<T>),
+    Edit {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        prepend: Vec<T>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        append: Vec<T>,
+        /// Ids of inherited items to drop
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        remove: Vec<String>,
+    },
+}
+
+/// Read side of [ListSpec], where edits reject unknown keys, since
+/// a misspelled `apend` would otherwise be an empty edit
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "a list, or a table of prepend, append and remove"
+)]
+enum ListSpecDe<T> {
+    Set(Vec<T>),
+    Edit(ListEditDe<T>),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, bound = "T: Deserialize<'de>")]
+struct ListEditDe<T> {
+    #[serde(default)]
+    prepend: Vec<T>,
+    #[serde(default)]
+    append: Vec<T>,
+    #[serde(default)]
+    remove: Vec<String>,
+}
+
+impl<T> From<ListSpecDe<T>> for ListSpec<T> {
+    fn from(spec: ListSpecDe<T>) -> Self {
+        match spec {
+            ListSpecDe::Set(list) => ListSpec::Set(list),
+            ListSpecDe::Edit(ListEditDe {
+                prepend,
+                append,
+                remove,
+            }) => ListSpec::Edit {
+                prepend,
+                append,
+                remove,
+            },
+        }
+    }
+}
+
+/// Omitted lists keep what is inherited
+impl<T> Default for ListSpec<T> {
+    fn default() -> Self {
+        ListSpec::Edit {
+            prepend: Vec::new(),
+            append: Vec::new(),
+            remove: Vec::new(),
+        }
+    }
+}
+
+/// Item of a [ListSpec], a layer id or a mod reference keyed by its mod id
+pub trait ListItem: Clone {
+    fn key(&self) -> &str;
+}
+
+impl ListItem for String {
+    fn key(&self) -> &str {
+        self
+    }
+}
+
+impl ListItem for (String, String) {
+    fn key(&self) -> &str {
+        &self.0
+    }
+}
+
+impl<T> ListSpec<T> {
+    /// Whether it keeps the inherited list as is
+    fn is_unchanged(&self) -> bool {
+        matches!(
+            self,
+            ListSpec::Edit { prepend, append, remove }
+                if prepend.is_empty() && append.is_empty() && remove.is_empty()
+        )
+    }
+}
+
+impl<T: ListItem> ListSpec<T> {
+    /// Apply to the inherited list. Prepended and appended items replace
+    /// inherited ones with the same id, which moves them.
+    pub fn apply(&self, base: &[T]) -> Vec<T> {
+        match self {
+            ListSpec::Set(items) => items.clone(),
+            ListSpec::Edit {
+                prepend,
+                append,
+                remove,
+            } => {
+                let kept = |item: &&T| {
+                    let key = item.key();
+                    !remove.iter().any(|r| r == key)
+                        && !prepend.iter().chain(append).any(|i| i.key() == key)
+                };
+                prepend
+                    .iter()
+                    .chain(base.iter().filter(kept))
+                    .chain(append)
+                    .cloned()
+                    .collect()
+            }
+        }
+    }
+
+    /// Have `item` in the resulting list, replacing any item with the same id
+    pub fn insert(&mut self, item: T) {
+        fn upsert<T: ListItem>(items: &mut Vec<T>, item: T) {
+            match items.iter_mut().find(|i| i.key() == item.key()) {
+                Some(i) => *i = item,
+                None => items.push(item),
+            }
+        }
+
+        match self {
+            ListSpec::Set(items) => upsert(items, item),
+            ListSpec::Edit {
+                prepend,
+                append,
+                remove,
+            } => {
+                remove.retain(|r| r != item.key());
+                if prepend.iter().any(|i| i.key() == item.key()) {
+                    upsert(prepend, item);
+                } else {
+                    upsert(append, item);
+                }
+            }
+        }
+    }
+
+    /// Have no item with `key` in the resulting list
+    pub fn remove(&mut self, key: &str) {
+        match self {
+            ListSpec::Set(items) => items.retain(|i| i.key() != key),
+            ListSpec::Edit {
+                prepend,
+                append,
+                remove,
+            } => {
+                prepend.retain(|i| i.key() != key);
+                append.retain(|i| i.key() != key);
+                if !remove.iter().any(|r| r == key) {
+                    remove.push(key.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Effective config of an instance, see [resolve_extends]
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedConfig {
     pub name: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
     pub index: String,
     pub layers: Vec<String>,
     pub mods: Vec<(String, String)>,
 }
 
+/// Apply the chain of `extends` of an instance, from its root down.
+/// Name, description, tags and index are overridden when set, layers and mods
+/// are applied as [ListSpec]s to the inherited ones. `enabled` and `hidden`
+/// are never inherited.
+/// Whether `id` extends `ancestor`, directly or through other instances
+fn extends_from(
+    id: &str,
+    ancestor: &str,
+    configs: &HashMap<&str, &SugarCubeInstanceConfig>,
+) -> bool {
+    let mut seen = HashSet::new();
+    let mut parent = configs.get(id).and_then(|c| c.extends.as_deref());
+    while let Some(current) = parent {
+        if current == ancestor {
+            return true;
+        }
+        if !seen.insert(current) {
+            return false;
+        }
+        parent = configs.get(current).and_then(|c| c.extends.as_deref());
+    }
+    false
+}
+
+pub fn resolve_extends(
+    id: &str,
+    configs: &HashMap<&str, &SugarCubeInstanceConfig>,
+) -> Result<ResolvedConfig, String> {
+    let mut chain = vec![
+        *configs
+            .get(id)
+            .ok_or_else(|| format!("Instance {id} not found"))?,
+    ];
+    while let Some(parent) = &chain[chain.len() - 1].extends {
+        if let Some(pos) = chain.iter().position(|c| c.id == *parent) {
+            let cycle = chain[pos..]
+                .iter()
+                .map(|c| c.id.as_str())
+                .chain([parent.as_str()])
+                .collect::<Vec<_>>();
+            return Err(format!(
+                "Instance {id} extends a cycle: {}",
+                cycle.join(" -> ")
+            ));
+        }
+        let config = configs.get(parent.as_str()).ok_or_else(|| {
+            format!(
+                "Instance {} extends {parent}, which is not found",
+                chain[chain.len() - 1].id
+            )
+        })?;
+        chain.push(config);
+    }
+
+    let mut resolved = ResolvedConfig::default();
+    for config in chain.iter().rev() {
+        if config.name.is_some() {
+            resolved.name = config.name.clone();
+        }
+        if config.description.is_some() {
+            resolved.description = config.description.clone();
+        }
+        if !config.tags.is_empty() {
+            resolved.tags = config.tags.clone();
+        }
+        if !config.index.is_empty() {
+            resolved.index = config.index.clone();
+        }
+        resolved.layers = config.layers.apply(&resolved.layers);
+        resolved.mods = config.mods.apply(&resolved.mods);
+    }
+    if resolved.index.is_empty() {
+        return Err(format!("Instance {id} has no index"));
+    }
+    Ok(resolved)
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PathResolution {
+    pub path: String,
+    /// Layer serving the path, none if not found or hidden
+    pub winner: Option<String>,
+    pub candidates: Vec<LayerCandidate>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayerCandidate {
+    pub layer: String,
+    pub size: u64,
+    pub etag: String,
+    /// Layer whose whiteout removed this candidate
+    pub hidden_by: Option<String>,
+}
+
+/// Content of `cache.bin` in the layer directory
 #[derive(Debug, Serialize, Deserialize)]
 struct LayerCache {
-    last_modified: SystemTime,
-    layer_map: LayerMap,
+    layers: HashMap<String, CachedLayer>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedLayer {
+    /// See [layer_fingerprint]
+    fingerprint: u64,
+    mfs: MapFileSystem,
 }
 
 pub(super) fn create_sc_info(
@@ -140,23 +1079,29 @@ pub(super) fn create_sc_info(
     name: Option<String>,
     use_mods: bool,
     use_save_sync_mod: bool,
+    save_sync_position: SaveSyncPosition,
+    verify_mods: VerifyMods,
+    lookup: &LookupConfig,
 ) -> Result<SugarCubeInfo> {
     let indexes = create_indexes(id)?;
     let layers = create_layers(id)?;
     let mods = if use_mods {
-        create_mods(id)?
+        create_mods(id, verify_mods)?
     } else {
         HashMap::new()
     };
 
-    let instances = create_instances(id, &indexes, &layers, &mods)?;
+    let instances = create_instances(id, &indexes, &layers, &mods, lookup, use_save_sync_mod)?;
 
     Ok(SugarCubeInfo {
         name,
         instances,
+        indexes,
+        layers,
         mods,
         use_mods,
         use_save_sync_mod,
+        save_sync_position,
     })
 }
 
@@ -165,6 +1110,8 @@ fn create_instances(
     index_map: &IndexMap,
     layer_map: &LayerMap,
     mod_map: &ModMap,
+    lookup: &LookupConfig,
+    use_save_sync_mod: bool,
 ) -> Result<InstanceMap> {
     let instance_dir = config_ref().instance_dir(id);
     if !instance_dir.exists() {
@@ -177,12 +1124,17 @@ fn create_instances(
         let example = SugarCubeInstanceConfig {
             id: "example".to_string(),
             name: Some("Example Instance".to_string()),
+            extends: None,
             index: "index".to_string(),
-            layers: vec!["layer1".to_string(), "layer2".to_string()],
-            mods: vec![
+            layers: ListSpec::Set(vec!["layer1".to_string(), "layer2".to_string()]),
+            mods: ListSpec::Set(vec![
                 ("mod1".to_string(), "1.0".to_string()),
                 ("mod2".to_string(), "0.3.9-test".to_string()),
-            ],
+            ]),
+            enabled: true,
+            hidden: false,
+            description: Some("Shown in the instance list".to_string()),
+            tags: vec![],
         };
         let example_path = instance_dir.join("_example.yaml");
         let example_str = format!(
@@ -199,99 +1151,133 @@ fn create_instances(
         return Ok(HashMap::new());
     }
 
-    let walker = WalkDir::new(&instance_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| e.file_name() != "_example.yaml")
-        .filter(|e| e.path().extension_eqs(&["json", "toml", "yaml", "yml"]));
+    let mut sources: Vec<(PathBuf, Option<Arc<InstanceDir>>)> = Vec::new();
+    let mut walker = WalkDir::new(&instance_dir).sort_by_file_name().into_iter();
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        if entry.file_type().is_dir() {
+            if entry.depth() > 0 && path.join(INSTANCE_DIR_CONFIG).is_file() {
+                // Files in its private folders are not instance configs
+                walker.skip_current_dir();
+                let dir = load_instance_dir(id, path)?;
+                sources.push((path.join(INSTANCE_DIR_CONFIG), Some(Arc::new(dir))));
+            }
+            continue;
+        }
+        if entry.file_type().is_file()
+            && entry.file_name() != "_example.yaml"
+            && path.extension_eqs(&["json", "toml", "yaml", "yml"])
+        {
+            sources.push((path.to_path_buf(), None));
+        }
+    }
 
-    let mut map = HashMap::new();
+    let mut configs: Vec<(PathBuf, Option<Arc<InstanceDir>>, SugarCubeInstanceConfig)> = Vec::new();
     let start = Instant::now();
 
-    for entry in walker {
-        let path = entry.path();
-        let lowercase_ext = path
-            .extension()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_lowercase();
-        let ext = lowercase_ext.as_str();
+    for (path, dir) in sources {
+        let instance_configs = read_instance_file(&path)
+            .map_err(|e| anyhow!("Invalid instance config {}: {e}", path.display()))?
+            .into_configs();
 
-        let content = match fs::read_to_string(path) {
-            Ok(content) => content,
-            Err(e) => {
-                error!("Error reading file {}: {}", path.display(), e);
+        for instance_config in instance_configs {
+            if let Some((other, ..)) = configs.iter().find(|(.., c)| c.id == instance_config.id) {
+                warn!(
+                    "Instance {} in {} is already defined in {}, skipping",
+                    instance_config.id,
+                    path.display(),
+                    other.display()
+                );
                 continue;
             }
-        };
-
-        let instance_config: SugarCubeInstanceConfig = match ext {
-            "json" => serde_json::from_str(content.as_str())?,
-            "toml" => toml::from_str(content.as_str())?,
-            "yaml" | "yml" => serde_yaml::from_str(content.as_str())?,
-            _ => {
+            if RESERVED_INSTANCE_IDS.contains(&instance_config.id.as_str()) {
                 warn!(
-                    "Unsupported file extension {} for instance config, why are you here?? : {}",
-                    ext,
+                    "Instance ID {} in {} is reserved, its API routes are unreachable",
+                    instance_config.id,
                     path.display()
                 );
+            }
+            configs.push((path.clone(), dir.clone(), instance_config));
+        }
+    }
+
+    // Resolved against every config, so that the order of files does not matter
+    let config_map = configs
+        .iter()
+        .map(|(.., c)| (c.id.as_str(), c))
+        .collect::<HashMap<_, _>>();
+    let mut map = HashMap::new();
+
+    for (path, dir, instance_config) in configs.iter() {
+        let private = dir.as_deref();
+        let resolved = match resolve_extends(&instance_config.id, &config_map) {
+            Ok(resolved) => resolved,
+            Err(problem) => {
+                error!("{problem}, skipping");
                 continue;
             }
         };
 
         // Resolving references
-        let index_ref = match index_map.get(&instance_config.index) {
+        let index_ref = match scoped(private.map(|d| &d.indexes), index_map, &resolved.index) {
             Some(r) => r,
             None => {
                 warn!(
                     "Index {} referenced by {} not found, skipping",
-                    instance_config.index, instance_config.id
+                    resolved.index, instance_config.id
                 );
                 continue;
             }
         };
 
-        let mut merged_layer_map = HashMap::new();
-        for layer_id in instance_config.layers.iter() {
-            if let Some(mfs) = layer_map.get(layer_id) {
-                for (k, v) in mfs.iter() {
-                    merged_layer_map.insert(k.clone(), v.clone());
-                }
-            }
-        }
-        let merged_mfs = MapFileSystem::new(merged_layer_map);
+        let merged_mfs = MapFileSystem::merge(resolved.layers.iter().filter_map(|layer_id| {
+            let layer = scoped(private.map(|d| &d.layers), layer_map, layer_id)?;
+            Some((layer_id.as_str(), layer))
+        }))
+        .with_lookup(lookup, &format!("instance {}", instance_config.id));
 
-        let mut mod_ref_map = HashMap::new();
+        let (mod_refs, problems) = collect_mod_refs(
+            &instance_config.id,
+            &resolved.mods,
+            mod_map,
+            use_save_sync_mod,
+        );
+        for problem in problems {
+            warn!("{problem}, skipping");
+        }
 
-        for (mod_id, mod_sub_id) in instance_config.mods.iter().cloned() {
-            if let Some(mod_subs) = mod_map.get(&mod_id) {
-                if let Some(mod_path) = mod_subs.get(&mod_sub_id) {
-                    mod_ref_map.insert((mod_id, mod_sub_id), mod_path.clone());
-                } else {
-                    warn!(
-                        "Mod {} with sub_id {} referenced by {} not found, skipping",
-                        mod_id, mod_sub_id, instance_config.id
-                    );
-                    continue;
-                }
-            } else {
-                warn!(
-                    "Mod {} referenced by {} not found, skipping",
-                    mod_id, instance_config.id
+        let mod_refs = match order_mods(mod_refs) {
+            Ok(mod_refs) => mod_refs,
+            Err(problems) => {
+                error!(
+                    "Mods of instance {} are inconsistent, skipping:\n  - {}",
+                    instance_config.id,
+                    problems.join("\n  - ")
                 );
                 continue;
             }
-        }
+        };
 
         let instance_id = instance_config.id.clone();
         let instance = SugarCubeInstance {
             id: instance_id.clone(),
-            name: instance_config.name.clone(),
+            name: resolved.name,
+            description: resolved.description,
+            tags: resolved.tags,
+            enabled: instance_config.enabled,
+            hidden: instance_config.hidden,
+            index: resolved.index,
+            layers: resolved.layers,
+            mods: resolved.mods,
             index_path: index_ref.clone(),
             layer_merged: merged_mfs,
-            mods_ref: mod_ref_map,
-            original_conf: instance_config,
+            mods_ref: mod_refs,
+            original_conf: instance_config.clone(),
+            source: path.clone(),
+            dir: dir.clone(),
         };
 
         map.insert(instance_id, instance);
@@ -314,6 +1300,218 @@ fn create_instances(
     Ok(map)
 }
 
+/// Resolve the mods of an instance against the repository, in configured order.
+/// Mods that cannot be used are left out and reported as problems.
+fn collect_mod_refs(
+    instance_id: &str,
+    mods: &[(String, String)],
+    mod_map: &ModMap,
+    use_save_sync_mod: bool,
+) -> (Vec<ModRef>, Vec<String>) {
+    let mut mod_refs: Vec<ModRef> = Vec::new();
+    let mut problems = Vec::new();
+
+    for (mod_id, mod_sub_id) in mods.iter().cloned() {
+        if mod_id == SSI_MOD_ID {
+            if !use_save_sync_mod {
+                problems.push(format!(
+                    "Mod {} referenced by {} requires 'use_save_sync'",
+                    mod_id, instance_id
+                ));
+            } else if !mod_refs.iter().any(|m| m.id == SSI_MOD_ID) {
+                // Built in, no manifest so that its dependencies are not enforced
+                mod_refs.push(ModRef {
+                    id: mod_id,
+                    sub_id: "0".to_string(),
+                    requested: mod_sub_id,
+                    path: PathBuf::new(),
+                    manifest: None,
+                });
+            }
+            continue;
+        }
+        let Some(mod_subs) = mod_map.get(&mod_id) else {
+            problems.push(format!(
+                "Mod {} referenced by {} not found",
+                mod_id, instance_id
+            ));
+            continue;
+        };
+        let Some((resolved, mod_entry)) = resolve_mod_version(mod_subs, &mod_sub_id) else {
+            problems.push(format!(
+                "Mod {} with sub_id or version {} referenced by {} not found",
+                mod_id, mod_sub_id, instance_id
+            ));
+            continue;
+        };
+        if let Some(error) = &mod_entry.error {
+            problems.push(format!(
+                "Mod {}:{} referenced by {} is broken: {}",
+                mod_id, resolved, instance_id, error
+            ));
+            continue;
+        }
+        if *resolved != mod_sub_id {
+            info!(
+                "Mod {} {} referenced by {} resolved to {}",
+                mod_id, mod_sub_id, instance_id, resolved
+            );
+        }
+        if mod_refs
+            .iter()
+            .any(|m| m.id == mod_id && m.sub_id == *resolved)
+        {
+            problems.push(format!(
+                "Mod {}:{} is referenced twice by {}",
+                mod_id, mod_sub_id, instance_id
+            ));
+            continue;
+        }
+        mod_refs.push(ModRef {
+            id: mod_id,
+            sub_id: resolved.clone(),
+            requested: mod_sub_id,
+            path: mod_entry.path.clone(),
+            manifest: mod_entry.manifest.clone(),
+        });
+    }
+
+    (mod_refs, problems)
+}
+
+/// Content of an instance config file, a single instance or a list of them
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum InstanceFile {
+    One(Box<SugarCubeInstanceConfig>),
+    /// A top level list, not possible in TOML
+    Many(Vec<SugarCubeInstanceConfig>),
+    /// `instances = [...]`, or `[[instances]]` tables in TOML
+    Table {
+        instances: Vec<SugarCubeInstanceConfig>,
+    },
+}
+
+impl InstanceFile {
+    fn into_configs(self) -> Vec<SugarCubeInstanceConfig> {
+        match self {
+            InstanceFile::One(config) => vec![*config],
+            InstanceFile::Many(instances) | InstanceFile::Table { instances } => instances,
+        }
+    }
+}
+
+fn config_format(path: &Path) -> String {
+    path.extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase()
+}
+
+fn read_instance_file(path: &Path) -> Result<InstanceFile> {
+    fn parse<T: serde::de::DeserializeOwned>(ext: &str, content: &str) -> Result<T> {
+        Ok(match ext {
+            "json" => serde_json::from_str(content)?,
+            "toml" => toml::from_str(content)?,
+            "yaml" | "yml" => serde_yaml::from_str(content)?,
+            _ => return Err(anyhow!("Unsupported instance config format: {ext}")),
+        })
+    }
+
+    let ext = config_format(path);
+    let content = fs::read_to_string(path)?;
+    // The shape is picked from the top level, so that errors come from that shape
+    Ok(match parse::<serde_json::Value>(&ext, &content)? {
+        serde_json::Value::Array(_) => InstanceFile::Many(parse(&ext, &content)?),
+        serde_json::Value::Object(table) if table.contains_key("instances") => {
+            #[derive(Deserialize)]
+            #[serde(deny_unknown_fields)]
+            struct Table {
+                instances: Vec<SugarCubeInstanceConfig>,
+            }
+            let Table { instances } = parse(&ext, &content)?;
+            InstanceFile::Table { instances }
+        }
+        _ => InstanceFile::One(Box::new(parse(&ext, &content)?)),
+    })
+}
+
+/// Write in the format given by the extension of `path`
+fn write_instance_file(path: &Path, file: &InstanceFile) -> Result<()> {
+    let ext = config_format(path);
+    let content = match ext.as_str() {
+        "json" => serde_json::to_string_pretty(file)?,
+        "toml" => toml::to_string_pretty(file)?,
+        "yaml" | "yml" => serde_yaml::to_string(file)?,
+        _ => {
+            return Err(anyhow!(
+                "Unsupported instance config format: {}",
+                path.display()
+            ));
+        }
+    };
+
+    // Replaced in one step, so a failed write never leaves a truncated config
+    let tmp_path = path.with_extension(format!("{ext}.tmp"));
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Write an instance config back to `path`, replacing the instance with the
+/// same id if the file has it, or adding it to the instances already there
+pub(crate) fn save_instance_config(path: &Path, config: &SugarCubeInstanceConfig) -> Result<()> {
+    let file = if path.exists() {
+        let mut file = read_instance_file(path)?;
+        match &mut file {
+            InstanceFile::One(existing) if existing.id == config.id => {
+                **existing = config.clone();
+                file
+            }
+            InstanceFile::One(_) => {
+                let mut instances = file.into_configs();
+                instances.push(config.clone());
+                if config_format(path) == "toml" {
+                    InstanceFile::Table { instances }
+                } else {
+                    InstanceFile::Many(instances)
+                }
+            }
+            InstanceFile::Many(instances) | InstanceFile::Table { instances } => {
+                match instances.iter_mut().find(|c| c.id == config.id) {
+                    Some(existing) => *existing = config.clone(),
+                    None => instances.push(config.clone()),
+                }
+                file
+            }
+        }
+    } else {
+        InstanceFile::One(Box::new(config.clone()))
+    };
+    write_instance_file(path, &file)
+}
+
+/// Drop an instance from `path`, deleting the file if it held only that one.
+/// The config of an instance directory is kept, possibly empty, so that
+/// the directory is not taken for a folder of plain instance files.
+fn remove_instance_config(path: &Path, instance_id: &str) -> Result<()> {
+    let is_dir_config = path.file_name().is_some_and(|n| n == INSTANCE_DIR_CONFIG);
+    match read_instance_file(path)? {
+        InstanceFile::One(_) if !is_dir_config => Ok(fs::remove_file(path)?),
+        InstanceFile::One(_) => {
+            write_instance_file(path, &InstanceFile::Table { instances: vec![] })
+        }
+        InstanceFile::Many(mut instances) => {
+            instances.retain(|c| c.id != instance_id);
+            write_instance_file(path, &InstanceFile::Many(instances))
+        }
+        InstanceFile::Table { mut instances } => {
+            instances.retain(|c| c.id != instance_id);
+            write_instance_file(path, &InstanceFile::Table { instances })
+        }
+    }
+}
+
 fn create_indexes(id: &str) -> Result<IndexMap> {
     let index_dir = config_ref().index_dir(id);
     if !index_dir.exists() {
@@ -324,8 +1522,12 @@ fn create_indexes(id: &str) -> Result<IndexMap> {
         fs::create_dir(&index_dir)?;
         return Ok(HashMap::new());
     }
+    scan_indexes(id, &index_dir)
+}
 
-    let walker = WalkDir::new(&index_dir)
+/// Index files in `index_dir` by their name without extension
+fn scan_indexes(id: &str, index_dir: &Path) -> Result<IndexMap> {
+    let walker = WalkDir::new(index_dir)
         .into_iter()
         .filter_map(|e| e.ok())
         .filter(|e| e.file_type().is_file())
@@ -371,66 +1573,70 @@ fn create_layers(id: &str) -> Result<LayerMap> {
         fs::create_dir(&layer_dir)?;
         return Ok(HashMap::new());
     }
+    scan_layers(id, &layer_dir, true)
+}
 
-    let start = Instant::now();
-
-    fn get_latest_modified_time(dir: impl AsRef<Path>) -> SystemTime {
-        let mut latest_time = fs::metadata(dir.as_ref())
-            .and_then(|m| m.modified())
-            .unwrap_or(SystemTime::UNIX_EPOCH);
+/// Every layer of a manage by label, the shared ones under its id and the
+/// private ones of instance directories under `{id}/{dir}`. Nothing is
+/// created on disk and the layer caches are only read.
+pub(crate) fn scan_all_layers(id: &str) -> Result<Vec<(String, LayerMap)>> {
+    let mut all = Vec::new();
+    let layer_dir = config_ref().layer_dir(id);
+    if layer_dir.is_dir() {
+        all.push((id.to_string(), scan_layers(id, &layer_dir, false)?));
+    }
 
-        for entry in WalkDir::new(dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_name() != "cache.bin")
+    let mut walker = WalkDir::new(config_ref().instance_dir(id))
+        .sort_by_file_name()
+        .into_iter();
+    while let Some(entry) = walker.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        if entry.file_type().is_dir()
+            && entry.depth() > 0
+            && path.join(INSTANCE_DIR_CONFIG).is_file()
         {
-            if let Ok(metadata) = entry.metadata() {
-                if let Ok(modified) = metadata.modified() {
-                    if modified > latest_time {
-                        latest_time = modified;
-                    }
-                }
+            walker.skip_current_dir();
+            let layer_dir = path.join(LAYER_DIR_NAME);
+            if layer_dir.is_dir() {
+                let label = format!("{id}/{}", entry.file_name().to_string_lossy());
+                let layers = scan_layers(&label, &layer_dir, false)?;
+                all.push((label, layers));
             }
         }
-
-        latest_time
     }
+    Ok(all)
+}
 
+/// Layers in `layer_dir` by name, each a directory or a zip,
+/// reusing unchanged ones from its `cache.bin`, which is updated if `persist`
+fn scan_layers(id: &str, layer_dir: &Path, persist: bool) -> Result<LayerMap> {
+    let start = Instant::now();
     let layer_cache_path = layer_dir.join("cache.bin");
-    let current_modified = get_latest_modified_time(&layer_dir);
 
-    if let Ok(cache_file) = fs::read(&layer_cache_path) {
-        if let Ok((cache, _)) =
-            bincode::serde::decode_from_slice::<LayerCache, Configuration>(&cache_file, standard())
-        {
-            if current_modified <= cache.last_modified {
-                info!(
-                    "Using cached layer map for {} with {} items, created on '{}' ({}ms)",
-                    id,
-                    cache.layer_map.len(),
-                    chrono::DateTime::<chrono::Local>::from(cache.last_modified)
-                        .format("%Y-%m-%d %H:%M:%S")
-                        .to_string(),
-                    start.elapsed().as_millis()
-                );
-                return Ok(cache.layer_map);
-            } else {
-                info!(
-                    "Cache for {} is outdated, last modified on '{}', current modified on '{}'",
-                    id,
-                    chrono::DateTime::<chrono::Local>::from(cache.last_modified)
-                        .format("%Y-%m-%d %H:%M:%S")
-                        .to_string(),
-                    chrono::DateTime::<chrono::Local>::from(current_modified)
-                        .format("%Y-%m-%d %H:%M:%S")
-                        .to_string()
-                );
+    let mut cached = match fs::read(&layer_cache_path) {
+        Ok(cache_file) => {
+            match decode_cache::<LayerCache>(LAYER_CACHE_KIND, LAYER_CACHE_VERSION, &cache_file) {
+                Ok(cache) => cache.layers,
+                Err(e) => {
+                    warn!(
+                        "Layer cache {} rejected, rescanning all layers: {}",
+                        layer_cache_path.display(),
+                        e
+                    );
+                    HashMap::new()
+                }
             }
         }
-    }
-    info!("No valid cache found for {}, creating new layer map", id);
+        Err(_) => {
+            info!("No layer cache found for {}, creating new layer map", id);
+            HashMap::new()
+        }
+    };
 
-    let layer_roots = layer_dir
+    let mut layer_roots = layer_dir
         .read_dir()
         .map_err(|e| {
             error!("Error reading mod directory {}: {}", layer_dir.display(), e);
@@ -443,33 +1649,88 @@ fn create_layers(id: &str) -> Result<LayerMap> {
                 })
                 .ok()
         })
-        .filter(|entry| entry.file_type().map_or(false, |ft| ft.is_dir()));
+        .filter(|entry| {
+            entry.file_type().is_ok_and(|ft| ft.is_dir())
+                || (entry.file_type().is_ok_and(|ft| ft.is_file())
+                    && entry.path().extension_eq("zip"))
+        })
+        .collect::<Vec<_>>();
+    layer_roots.sort_by_key(|entry| entry.file_name());
+    // A dir takes precedence over a zip of the same layer name
+    let dir_names = layer_roots
+        .iter()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name())
+        .collect::<HashSet<_>>();
 
     let mut map = HashMap::new();
+    let mut cache = LayerCache {
+        layers: HashMap::new(),
+    };
+    let mut rescanned = 0;
 
     for entry in layer_roots {
         let path = entry.path();
-        let name = entry.file_name().to_string_lossy().to_string();
-        let now = Instant::now();
-        let mfs = match MapFileSystem::new_dir(&path) {
-            Ok(mfs) => {
-                info!(
-                    "Initialized MFS by dir '{}' in {}ms",
-                    name,
-                    now.elapsed().as_millis()
-                );
-                mfs
-            }
-            Err(e) => {
-                error!(
-                    "Error creating MapFileSystem in {}, skipping: {}",
-                    path.display(),
-                    e
-                );
-                continue;
+        let is_zip = path.is_file();
+        let name = if is_zip {
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        } else {
+            entry.file_name().to_string_lossy().to_string()
+        };
+        if is_zip && dir_names.contains(OsStr::new(&name)) {
+            warn!(
+                "Layer '{}' is defined by both a dir and a zip, ignoring {}",
+                name,
+                path.display()
+            );
+            continue;
+        }
+        // Keyed by file name, so a dir and a zip never share a cache entry
+        let key = entry.file_name().to_string_lossy().to_string();
+        let fingerprint = layer_fingerprint(&path);
+
+        let mfs = match cached.remove(&key) {
+            Some(layer) if layer.fingerprint == fingerprint => layer.mfs,
+            previous => {
+                let now = Instant::now();
+                let mfs = if is_zip {
+                    MapFileSystem::new_zip(&path)
+                } else {
+                    MapFileSystem::new_dir(&path, previous.as_ref().map(|layer| &layer.mfs))
+                };
+                match mfs {
+                    Ok(mfs) => {
+                        info!(
+                            "Initialized MFS by {} '{}' in {}ms",
+                            if is_zip { "zip" } else { "dir" },
+                            name,
+                            now.elapsed().as_millis()
+                        );
+                        rescanned += 1;
+                        mfs
+                    }
+                    Err(e) => {
+                        error!(
+                            "Error creating MapFileSystem in {}, skipping: {}",
+                            path.display(),
+                            e
+                        );
+                        continue;
+                    }
+                }
             }
         };
 
+        cache.layers.insert(
+            key,
+            CachedLayer {
+                fingerprint,
+                mfs: mfs.clone(),
+            },
+        );
         map.insert(name, mfs);
     }
 
@@ -481,30 +1742,51 @@ fn create_layers(id: &str) -> Result<LayerMap> {
         );
     } else {
         info!(
-            "Created {} layers for {} in {}ms",
+            "Created {} layers for {} in {}ms, {} reused from cache",
             map.len(),
             id,
-            start.elapsed().as_millis()
+            start.elapsed().as_millis(),
+            map.len() - rescanned
         );
+    }
 
-        let cache = LayerCache {
-            last_modified: current_modified,
-            layer_map: map.clone(),
-        };
-
-        if let Ok(content) = bincode::serde::encode_to_vec(cache, standard()) {
-            fs::write(layer_cache_path, content)?;
-        } else {
-            error!(
-                "Error writing layer cache to {}",
-                layer_cache_path.display()
-            );
+    // Layers left in the old cache were removed from disk
+    if persist && (rescanned > 0 || !cached.is_empty()) {
+        match encode_cache(LAYER_CACHE_KIND, LAYER_CACHE_VERSION, &cache) {
+            Ok(content) => fs::write(layer_cache_path, content)?,
+            Err(e) => error!(
+                "Error writing layer cache to {}: {}",
+                layer_cache_path.display(),
+                e
+            ),
         }
     }
     Ok(map)
 }
 
-fn create_mods(id: &str) -> Result<ModMap> {
+/// Fingerprint of a layer root from the path, size and modification time of
+/// every entry, so that edits, additions and removals all change it
+fn layer_fingerprint(root: &Path) -> u64 {
+    let mut hasher = Xxh3::new();
+    for entry in WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        hasher.update(entry.path().as_os_str().as_encoded_bytes());
+        if let Ok(metadata) = entry.metadata() {
+            hasher.update(&metadata.len().to_le_bytes());
+            if let Ok(modified) = metadata.modified()
+                && let Ok(duration) = modified.duration_since(SystemTime::UNIX_EPOCH)
+            {
+                hasher.update(&duration.as_nanos().to_le_bytes());
+            }
+        }
+    }
+    hasher.digest()
+}
+
+fn create_mods(id: &str, verify: VerifyMods) -> Result<ModMap> {
     let mod_dir = config_ref().mod_dir(id);
     if !mod_dir.exists() {
         warn!(
@@ -516,6 +1798,7 @@ fn create_mods(id: &str) -> Result<ModMap> {
     }
 
     let mut repo = HashMap::new();
+    let mut manifests = ManifestStore::open(mod_dir.join("cache.bin"), verify);
     let start = Instant::now();
 
     let mod_roots = mod_dir
@@ -531,7 +1814,7 @@ fn create_mods(id: &str) -> Result<ModMap> {
                 })
                 .ok()
         })
-        .filter(|entry| entry.file_type().map_or(false, |ft| ft.is_dir()));
+        .filter(|entry| entry.file_type().is_ok_and(|ft| ft.is_dir()));
 
     for entry in mod_roots {
         let mod_id = entry.file_name().to_string_lossy().to_string();
@@ -549,12 +1832,13 @@ fn create_mods(id: &str) -> Result<ModMap> {
                 } else {
                     filename.to_string()
                 };
-                (name, path)
+                (name, manifests.load(path))
             })
-            .collect::<HashMap<String, PathBuf>>();
+            .collect::<HashMap<String, ModEntry>>();
 
         repo.insert(mod_id, mod_files);
     }
+    manifests.save();
 
     if repo.is_empty() {
         warn!("No valid mods found for {} in {}", id, mod_dir.display());
@@ -578,12 +1862,17 @@ mod test {
         let config = SugarCubeInstanceConfig {
             id: "test".to_string(),
             name: Some("Test Instance".to_string()),
+            extends: None,
             index: "index".to_string(),
-            layers: vec!["layer1".to_string(), "layer2".to_string()],
-            mods: vec![
+            layers: ListSpec::Set(vec!["layer1".to_string(), "layer2".to_string()]),
+            mods: ListSpec::Set(vec![
                 ("mod1".to_string(), "1.0".to_string()),
                 ("mod2".to_string(), "1.3.0".to_string()),
-            ],
+            ]),
+            enabled: true,
+            hidden: false,
+            description: None,
+            tags: vec!["test".to_string()],
         };
 
         let ser_toml = toml::to_string_pretty(&config).unwrap();
@@ -595,4 +1884,234 @@ mod test {
         let ser_json = serde_json::to_string_pretty(&config).unwrap();
         println!("Serialized json: {}", ser_json);
     }
+
+    #[test]
+    fn test_list_spec() {
+        let parse = |yaml: &str| serde_yaml::from_str::<SugarCubeInstanceConfig>(yaml);
+        let config = parse("id: a\nlayers: { append: [l1] }\nmods: {}").unwrap();
+        assert_eq!(
+            config.layers,
+            ListSpec::Edit {
+                prepend: vec![],
+                append: vec!["l1".to_string()],
+                remove: vec![],
+            }
+        );
+        assert!(config.mods.is_unchanged());
+
+        // A misspelled key must not pass as an empty edit
+        assert!(parse("id: a\nlayers: { apend: [l1] }").is_err());
+        assert!(
+            toml::from_str::<SugarCubeInstanceConfig>("id = \"a\"\nmods = { remve = [\"m\"] }")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_resolve_extends() {
+        let parse = |yaml: &str| serde_yaml::from_str::<SugarCubeInstanceConfig>(yaml).unwrap();
+        let base = parse(
+            "id: base\nname: Base\nindex: game\nlayers: [l1, l2]\nmods: [[a, '1'], [b, '1']]",
+        );
+        let child = parse(
+            "id: child\nextends: base\nlayers: { prepend: [l0], append: [l3], remove: [l2] }\n\
+             mods: { append: [[a, '2'], [c, '1']], remove: [b] }",
+        );
+        let grandchild = parse("id: grandchild\nextends: child\nname: Grand\nlayers: [l9]");
+        let configs = [&base, &child, &grandchild]
+            .into_iter()
+            .map(|c| (c.id.as_str(), c))
+            .collect::<HashMap<_, _>>();
+
+        let resolved = resolve_extends("child", &configs).unwrap();
+        assert_eq!(resolved.name.as_deref(), Some("Base"));
+        assert_eq!(resolved.index, "game");
+        assert_eq!(resolved.layers, ["l0", "l1", "l3"]);
+        assert_eq!(
+            resolved.mods,
+            [
+                ("a".to_string(), "2".to_string()),
+                ("c".to_string(), "1".to_string())
+            ]
+        );
+
+        let resolved = resolve_extends("grandchild", &configs).unwrap();
+        assert_eq!(resolved.name.as_deref(), Some("Grand"));
+        assert_eq!(resolved.layers, ["l9"]);
+        assert_eq!(resolved.mods.len(), 2);
+
+        assert!(extends_from("grandchild", "base", &configs));
+        assert!(!extends_from("base", "child", &configs));
+
+        let looped = parse("id: base\nextends: grandchild\nindex: game");
+        let mut configs = configs;
+        configs.insert("base", &looped);
+        let err = resolve_extends("child", &configs).unwrap_err();
+        assert!(
+            err.contains("child -> base -> grandchild -> child"),
+            "{err}"
+        );
+        assert!(!extends_from("child", "other", &configs));
+    }
+
+    #[test]
+    fn test_save_instance_config() {
+        let root = std::env::temp_dir().join(format!("uni-instance-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let config = SugarCubeInstanceConfig {
+            id: "test".to_string(),
+            name: None,
+            extends: Some("base".to_string()),
+            index: String::new(),
+            layers: ListSpec::Edit {
+                prepend: vec![],
+                append: vec!["layer1".to_string()],
+                remove: vec!["layer0".to_string()],
+            },
+            mods: ListSpec::Set(vec![("mod1".to_string(), "^1".to_string())]),
+            enabled: false,
+            hidden: true,
+            description: None,
+            tags: vec![],
+        };
+
+        for ext in ["json", "toml", "yaml"] {
+            let path = root.join(format!("test.{ext}"));
+            save_instance_config(&path, &config).unwrap();
+            let content = fs::read_to_string(&path).unwrap();
+            let read: SugarCubeInstanceConfig = match ext {
+                "json" => serde_json::from_str(&content).unwrap(),
+                "toml" => toml::from_str(&content).unwrap(),
+                _ => serde_yaml::from_str(&content).unwrap(),
+            };
+            assert_eq!(read.layers, config.layers);
+            assert!(!read.enabled && read.hidden);
+            assert_eq!(read.mods, config.mods);
+        }
+        // A second instance turns the file into a list
+        let other = SugarCubeInstanceConfig {
+            id: "other".to_string(),
+            ..config.clone()
+        };
+        for (ext, table) in [("toml", true), ("yaml", false)] {
+            let path = root.join(format!("test.{ext}"));
+            save_instance_config(&path, &other).unwrap();
+            let file = read_instance_file(&path).unwrap();
+            assert_eq!(matches!(file, InstanceFile::Table { .. }), table);
+            assert_eq!(file.into_configs().len(), 2);
+
+            remove_instance_config(&path, "other").unwrap();
+            let configs = read_instance_file(&path).unwrap().into_configs();
+            assert_eq!(configs.len(), 1);
+            assert_eq!(configs[0].id, "test");
+        }
+        assert!(save_instance_config(&root.join("test.txt"), &config).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_read_instance_file() {
+        let root = std::env::temp_dir().join(format!("uni-instances-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let write = |name: &str, content: &str| {
+            let path = root.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+
+        let many = write("many.yaml", "- id: a\n  index: i\n- id: b\n");
+        let table = write(
+            "table.toml",
+            "[[instances]]\nid = \"a\"\n\n[[instances]]\nid = \"b\"\n",
+        );
+        let one = write("one.json", r#"{"id": "a", "index": "i"}"#);
+        assert!(matches!(
+            read_instance_file(&many),
+            Ok(InstanceFile::Many(_))
+        ));
+        assert!(matches!(
+            read_instance_file(&table),
+            Ok(InstanceFile::Table { .. })
+        ));
+        assert!(matches!(read_instance_file(&one), Ok(InstanceFile::One(_))));
+
+        // A bad entry reports the error of the list, not of a single instance
+        let bad = write("bad.yaml", "- id: a\n- id: b\n  layers: 1\n");
+        let err = read_instance_file(&bad).unwrap_err().to_string();
+        assert!(err.contains("[1]"), "{err}");
+        let bad = write("bad.toml", "[[instances]]\nid = \"a\"\nenabled = \"yes\"\n");
+        let err = read_instance_file(&bad).unwrap_err().to_string();
+        assert!(err.contains("expected a boolean"), "{err}");
+
+        let config = SugarCubeInstanceConfig {
+            id: "b".to_string(),
+            name: Some("B".to_string()),
+            extends: None,
+            index: String::new(),
+            layers: ListSpec::default(),
+            mods: ListSpec::default(),
+            enabled: true,
+            hidden: false,
+            description: None,
+            tags: vec![],
+        };
+        let c = SugarCubeInstanceConfig {
+            id: "c".to_string(),
+            ..config.clone()
+        };
+        for path in [&many, &table] {
+            save_instance_config(path, &config).unwrap();
+            save_instance_config(path, &c).unwrap();
+            let file = read_instance_file(path).unwrap();
+            assert_eq!(matches!(file, InstanceFile::Many(_)), path == &many);
+            let configs = file.into_configs();
+            let ids = configs.iter().map(|c| c.id.as_str()).collect::<Vec<_>>();
+            assert_eq!(ids, ["a", "b", "c"]);
+            assert_eq!(configs[1].name.as_deref(), Some("B"));
+
+            remove_instance_config(path, "a").unwrap();
+            remove_instance_config(path, "b").unwrap();
+            remove_instance_config(path, "c").unwrap();
+            let file = read_instance_file(path).unwrap();
+            assert_eq!(matches!(file, InstanceFile::Many(_)), path == &many);
+            assert!(file.into_configs().is_empty());
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(
+            sanitize_file_name("ModLoader GUI").as_deref(),
+            Some("ModLoader_GUI")
+        );
+        assert_eq!(
+            sanitize_file_name("1.2.0-beta").as_deref(),
+            Some("1.2.0-beta")
+        );
+        assert_eq!(sanitize_file_name("../../etc").as_deref(), Some("_.._etc"));
+        assert_eq!(sanitize_file_name("..."), None);
+        assert_eq!(sanitize_file_name("\u{6a21}\u{7ec4}"), None);
+        assert_eq!(sanitize_file_name("\u{6a21}\u{7ec4} UI"), None);
+        assert!(checked_file_name("a/b").is_err());
+        assert!(checked_file_name("1.0").is_ok());
+    }
+
+    #[test]
+    fn test_layer_fingerprint() {
+        let root = std::env::temp_dir().join(format!("uni-layer-{}", std::process::id()));
+        fs::create_dir_all(root.join("img")).unwrap();
+        fs::write(root.join("img/a.png"), b"a").unwrap();
+        fs::write(root.join("img/b.png"), b"b").unwrap();
+
+        let before = layer_fingerprint(&root);
+        assert_eq!(before, layer_fingerprint(&root));
+
+        // Removing a file never raises the latest mtime of the files left
+        fs::remove_file(root.join("img/b.png")).unwrap();
+        let after = layer_fingerprint(&root);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_ne!(before, after);
+    }
 }
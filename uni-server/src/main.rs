@@ -2,15 +2,17 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use axum::Router;
+use cli::{Command, dedup_report, parse_args};
 use element::load_data_dir;
 use routes::main_routes;
 use tokio::net::TcpListener;
 use tracing::info;
 use util::{
-    config::{config_ref, ReadConfig},
     AppState,
+    config::{ReadConfig, config_ref},
 };
 
+mod cli;
 mod constants;
 mod element;
 mod routes;
@@ -20,6 +22,11 @@ mod util;
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
+    match parse_args(std::env::args().skip(1))? {
+        Command::Serve => {}
+        Command::DedupReport { top } => return dedup_report(top),
+    }
+
     info!("Loading config...");
     let loaded_mapping = load_data_dir()?;
 
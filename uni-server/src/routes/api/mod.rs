@@ -7,8 +7,17 @@ use axum::{Json, Router};
 use serde::Serialize;
 use std::sync::Arc;
 
+mod sc;
+
 pub(super) fn routes() -> Router<Arc<AppState>> {
-    Router::new().route("/list-all", get(api_list_playable))
+    Router::new()
+        .route("/list-all", get(api_list_playable))
+        .route("/content-cache", get(api_content_cache))
+        .nest("/sc", sc::routes())
+}
+
+async fn api_content_cache(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.content_cache().stats()).into_response()
 }
 
 async fn api_list_playable(State(state): State<Arc<AppState>>) -> impl IntoResponse {
@@ -27,18 +36,24 @@ async fn api_list_playable(State(state): State<Arc<AppState>>) -> impl IntoRespo
     }
 
     #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
     struct SugarCubeLabel {
         id: String,
         name: Option<String>,
+        description: Option<String>,
+        tags: Vec<String>,
         index: String,
         layers: Vec<String>,
         mods: Option<Vec<(String, String)>>,
+        /// In load order, as `(mod_id, requested, resolved)`
+        resolved_mods: Option<Vec<(String, String, String)>>,
     }
 
     let mut list = state
         .iter()
+        .into_iter()
         .map(|(id, lt)| {
-            let (manage, name) = match lt {
+            let (manage, name) = match &*lt {
                 LoadedType::Plain { original_ref, .. } => (
                     PlayableType::Plain("0".to_string()),
                     original_ref.name.clone(),
@@ -47,13 +62,33 @@ async fn api_list_playable(State(state): State<Arc<AppState>>) -> impl IntoRespo
                     PlayableType::SugarCube(
                         info.instances
                             .iter()
+                            .filter(|(_, instance)| instance.enabled && !instance.hidden)
                             .map(|(key, instance)| SugarCubeLabel {
                                 id: key.to_string(),
                                 name: instance.name.clone(),
-                                index: instance.original_conf.index.to_string(),
-                                layers: instance.original_conf.layers.clone(),
+                                description: instance.description.clone(),
+                                tags: instance.tags.clone(),
+                                index: instance.index.clone(),
+                                layers: instance.layers.clone(),
                                 mods: if info.use_mods {
-                                    Some(instance.original_conf.mods.clone())
+                                    Some(instance.mods.clone())
+                                } else {
+                                    None
+                                },
+                                resolved_mods: if info.use_mods {
+                                    Some(
+                                        instance
+                                            .mods_ref
+                                            .iter()
+                                            .map(|m| {
+                                                (
+                                                    m.id.clone(),
+                                                    m.requested.clone(),
+                                                    m.sub_id.clone(),
+                                                )
+                                            })
+                                            .collect(),
+                                    )
                                 } else {
                                     None
                                 },
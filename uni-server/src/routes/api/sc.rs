@@ -0,0 +1,446 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+};
+use globset::Glob;
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{
+    constants::MOD_UPLOAD_LIMIT,
+    element::{
+        modloader::ModManifest,
+        sc::{SugarCubeInfo, SugarCubeInstanceConfig},
+    },
+    util::{AppState, admin::Admin, extract::ExtractInfo},
+};
+
+pub(super) fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/{manage_id}/{instance_id}/resolve", get(api_sc_resolve))
+        .route("/{manage_id}/{instance_id}/tree", get(api_sc_tree))
+        .route(
+            "/{manage_id}/mods",
+            get(api_sc_mods)
+                .post(api_sc_mod_upload)
+                .layer(DefaultBodyLimit::max(MOD_UPLOAD_LIMIT)),
+        )
+        .route(
+            "/{manage_id}/mods/{mod_id}/{mod_sub_id}",
+            delete(api_sc_mod_delete),
+        )
+        .route("/{manage_id}/{instance_id}/mods", post(api_sc_mod_attach))
+        .route(
+            "/{manage_id}/{instance_id}/mods/{mod_id}",
+            delete(api_sc_mod_detach),
+        )
+        .route("/{manage_id}/instances", post(api_sc_instance_create))
+        .route(
+            "/{manage_id}/instances/{instance_id}",
+            get(api_sc_instance)
+                .put(api_sc_instance_update)
+                .delete(api_sc_instance_delete),
+        )
+        .route(
+            "/{manage_id}/instances/{instance_id}/clone",
+            post(api_sc_instance_clone),
+        )
+        .route("/{manage_id}/diagnostics", get(api_sc_diagnostics))
+}
+
+/// Pick up changes made on disk, so they are served right away
+#[allow(clippy::result_large_err)]
+fn reload(state: &AppState, manage_id: &str) -> Result<(), Response> {
+    state.reload(manage_id).map(|_| ()).map_err(|err| {
+        error!("Failed to reload {manage_id}: {err}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Saved, but failed to reload {manage_id}: {err}"),
+        )
+            .into_response()
+    })
+}
+
+/// Change the files of a manage under the admin lock and reload it, on a
+/// blocking thread since both touch the disk
+#[allow(clippy::result_large_err)]
+async fn modify<T: Send + 'static>(
+    state: Arc<AppState>,
+    manage_id: String,
+    change: impl FnOnce(&SugarCubeInfo, &str) -> Result<T, Response> + Send + 'static,
+) -> Result<T, Response> {
+    tokio::task::spawn_blocking(move || {
+        let _guard = state.admin_lock();
+        let info = state.extract_sc_info(&manage_id)?;
+        let changed = change(&info, &manage_id)?;
+        reload(&state, &manage_id)?;
+        Ok(changed)
+    })
+    .await
+    .unwrap_or_else(|err| {
+        error!("Admin task panicked: {err}");
+        Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveQuery {
+    path: String,
+}
+
+async fn api_sc_resolve(
+    Path((manage_id, instance_id)): Path<(String, String)>,
+    Query(query): Query<ResolveQuery>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let info = match state.extract_sc_info(&manage_id) {
+        Ok(info) => info,
+        Err(res) => return res,
+    };
+    let path = query.path.trim_start_matches('/');
+
+    match info.resolve_path(&instance_id, path) {
+        Ok(resolution) => Json(resolution).into_response(),
+        Err(res) => res,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TreeQuery {
+    prefix: Option<String>,
+    glob: Option<String>,
+}
+
+async fn api_sc_tree(
+    Path((manage_id, instance_id)): Path<(String, String)>,
+    Query(query): Query<TreeQuery>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let info = match state.extract_sc_info(&manage_id) {
+        Ok(info) => info,
+        Err(res) => return res,
+    };
+    let instance = match info.get_instance(&instance_id) {
+        Some(instance) => instance,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                format!("Instance ID {instance_id} not found"),
+            )
+                .into_response();
+        }
+    };
+
+    let prefix = query
+        .prefix
+        .as_deref()
+        .unwrap_or_default()
+        .trim_start_matches('/');
+    let matcher = match query.glob.as_deref().map(Glob::new).transpose() {
+        Ok(glob) => glob.map(|glob| glob.compile_matcher()),
+        Err(err) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid glob: {err}")).into_response();
+        }
+    };
+
+    let tree = instance
+        .layer_merged
+        .tree(|path| path.starts_with(prefix) && matcher.as_ref().is_none_or(|m| m.is_match(path)));
+
+    Json(tree).into_response()
+}
+
+async fn api_sc_mods(
+    Path(manage_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ModInfo<'a> {
+        id: &'a str,
+        sub_id: &'a str,
+        size: Option<u64>,
+        manifest: Option<&'a ModManifest>,
+        error: Option<&'a str>,
+    }
+
+    let info = match state.extract_sc_info(&manage_id) {
+        Ok(info) => info,
+        Err(res) => return res,
+    };
+
+    let mut list = info
+        .mods
+        .iter()
+        .flat_map(|(id, subs)| {
+            subs.iter().map(move |(sub_id, entry)| ModInfo {
+                id,
+                sub_id,
+                size: std::fs::metadata(&entry.path).map(|m| m.len()).ok(),
+                manifest: entry.manifest.as_ref(),
+                error: entry.error.as_deref(),
+            })
+        })
+        .collect::<Vec<_>>();
+    list.sort_by(|a, b| (a.id, a.sub_id).cmp(&(b.id, b.sub_id)));
+
+    Json(list).into_response()
+}
+
+/// Admin only, since it exposes paths on the server
+async fn api_sc_diagnostics(
+    _: Admin,
+    Path(manage_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Diagnostics<'a> {
+        broken_mods: Vec<BrokenMod<'a>>,
+    }
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct BrokenMod<'a> {
+        id: &'a str,
+        sub_id: &'a str,
+        path: String,
+        error: &'a str,
+    }
+
+    let info = match state.extract_sc_info(&manage_id) {
+        Ok(info) => info,
+        Err(res) => return res,
+    };
+
+    let mut broken_mods = info
+        .mods
+        .iter()
+        .flat_map(|(id, subs)| {
+            subs.iter().filter_map(move |(sub_id, entry)| {
+                Some(BrokenMod {
+                    id,
+                    sub_id,
+                    path: entry.path.to_string_lossy().to_string(),
+                    error: entry.error.as_deref()?,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    broken_mods.sort_by(|a, b| (a.id, a.sub_id).cmp(&(b.id, b.sub_id)));
+
+    Json(Diagnostics { broken_mods }).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadQuery {
+    /// Overrides the mod name in `boot.json`
+    id: Option<String>,
+    /// Overrides the version in `boot.json`
+    version: Option<String>,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+async fn api_sc_mod_upload(
+    _: Admin,
+    Path(manage_id): Path<String>,
+    Query(query): Query<UploadQuery>,
+    State(state): State<Arc<AppState>>,
+    body: Bytes,
+) -> impl IntoResponse {
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Installed {
+        id: String,
+        sub_id: String,
+    }
+
+    #[allow(clippy::result_large_err)]
+    let installed = modify(state, manage_id, move |info, manage_id| {
+        info.install_mod(
+            manage_id,
+            &body,
+            query.id.as_deref(),
+            query.version.as_deref(),
+            query.overwrite,
+        )
+    })
+    .await;
+    match installed {
+        Ok((id, sub_id)) => (StatusCode::CREATED, Json(Installed { id, sub_id })).into_response(),
+        Err(res) => res,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteQuery {
+    /// Delete even if instances use the mod, they lose it on reload
+    #[serde(default)]
+    force: bool,
+}
+
+async fn api_sc_mod_delete(
+    _: Admin,
+    Path((manage_id, mod_id, mod_sub_id)): Path<(String, String, String)>,
+    Query(query): Query<DeleteQuery>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    #[allow(clippy::result_large_err)]
+    let removed = modify(state, manage_id, move |info, _| {
+        info.remove_mod(&mod_id, &mod_sub_id, query.force)
+    })
+    .await;
+    match removed {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(res) => res,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AttachBody {
+    id: String,
+    /// Version, range or `latest`, which is the default
+    version: Option<String>,
+}
+
+async fn api_sc_mod_attach(
+    _: Admin,
+    Path((manage_id, instance_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<AttachBody>,
+) -> impl IntoResponse {
+    #[allow(clippy::result_large_err)]
+    let attached = modify(state, manage_id, move |info, _| {
+        let requested = body.version.as_deref().unwrap_or("latest");
+        info.attach_mod(&instance_id, &body.id, requested)
+    })
+    .await;
+    match attached {
+        Ok(config) => Json(config).into_response(),
+        Err(res) => res,
+    }
+}
+
+async fn api_sc_mod_detach(
+    _: Admin,
+    Path((manage_id, instance_id, mod_id)): Path<(String, String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    #[allow(clippy::result_large_err)]
+    let detached = modify(state, manage_id, move |info, _| {
+        info.detach_mod(&instance_id, &mod_id)
+    })
+    .await;
+    match detached {
+        Ok(config) => Json(config).into_response(),
+        Err(res) => res,
+    }
+}
+
+async fn api_sc_instance(
+    Path((manage_id, instance_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let info = match state.extract_sc_info(&manage_id) {
+        Ok(info) => info,
+        Err(res) => return res,
+    };
+
+    match info.get_instance(&instance_id) {
+        Some(instance) => Json(&instance.original_conf).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            format!("Instance ID {instance_id} not found"),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateQuery {
+    /// File format of the new config, `yaml` by default
+    format: Option<String>,
+}
+
+async fn api_sc_instance_create(
+    _: Admin,
+    Path(manage_id): Path<String>,
+    Query(query): Query<CreateQuery>,
+    State(state): State<Arc<AppState>>,
+    Json(config): Json<SugarCubeInstanceConfig>,
+) -> impl IntoResponse {
+    #[allow(clippy::result_large_err)]
+    let created = modify(state, manage_id, move |info, manage_id| {
+        let format = query.format.as_deref().unwrap_or("yaml");
+        info.create_instance(manage_id, config, format)
+    })
+    .await;
+    match created {
+        Ok(config) => (StatusCode::CREATED, Json(config)).into_response(),
+        Err(res) => res,
+    }
+}
+
+async fn api_sc_instance_update(
+    _: Admin,
+    Path((manage_id, instance_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Json(config): Json<SugarCubeInstanceConfig>,
+) -> impl IntoResponse {
+    #[allow(clippy::result_large_err)]
+    let updated = modify(state, manage_id, move |info, _| {
+        info.update_instance(&instance_id, config)
+    })
+    .await;
+    match updated {
+        Ok(config) => Json(config).into_response(),
+        Err(res) => res,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CloneBody {
+    id: String,
+    /// Name of the copy, the original name is kept if unset
+    name: Option<String>,
+}
+
+async fn api_sc_instance_clone(
+    _: Admin,
+    Path((manage_id, instance_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+    Json(body): Json<CloneBody>,
+) -> impl IntoResponse {
+    #[allow(clippy::result_large_err)]
+    let cloned = modify(state, manage_id, move |info, _| {
+        info.clone_instance(&instance_id, &body.id, body.name)
+    })
+    .await;
+    match cloned {
+        Ok(config) => (StatusCode::CREATED, Json(config)).into_response(),
+        Err(res) => res,
+    }
+}
+
+async fn api_sc_instance_delete(
+    _: Admin,
+    Path((manage_id, instance_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    #[allow(clippy::result_large_err)]
+    let deleted = modify(state, manage_id, move |info, _| {
+        info.delete_instance(&instance_id)
+    })
+    .await;
+    match deleted {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(res) => res,
+    }
+}
@@ -1,21 +1,21 @@
 use std::sync::Arc;
 
 use axum::{
+    Router,
     http::{
-        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG}, HeaderMap,
-        StatusCode,
+        HeaderMap, StatusCode,
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG},
     },
     response::IntoResponse,
     routing::get,
-    Router,
 };
 use lazy_static::lazy_static;
 
 use crate::{
     constants::CACHE_HEADER,
     util::{
-        etag::{etag_check, etag_hash},
         AppState,
+        etag::{etag_hash, etag_match},
     },
 };
 
@@ -40,7 +40,7 @@ pub fn main_routes() -> Router<Arc<AppState>> {
 }
 
 async fn favicon(headers: HeaderMap) -> impl IntoResponse {
-    if let Some(res) = etag_check(ICON, &headers) {
+    if let Some(res) = etag_match(&ICON_ETAG, CACHE_HEADER, &headers) {
         return res;
     }
 
@@ -1,28 +1,32 @@
 use axum::routing::post;
 use axum::{
-    extract::{Path, State}, http::{
-        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG}, HeaderMap,
-        StatusCode,
+    Json, Router,
+    body::Bytes,
+    extract::{Path, State},
+    http::{
+        HeaderMap, HeaderValue, StatusCode,
+        header::{CONTENT_ENCODING, RANGE, VARY},
     },
-    response::{Html, IntoResponse, Response},
+    response::{IntoResponse, Response},
     routing::get,
-    Json,
-    Router,
 };
 use chrono::Local;
 use serde::Deserialize;
 use std::{fs, path::PathBuf, sync::Arc};
 use tracing::{error, info, warn};
 
-use crate::util::config::{config_ref, ReadConfig};
+use crate::util::config::{ReadConfig, config_ref};
 use crate::util::path_ext::PathHelper;
 use crate::{
-    constants::CACHE_HEADER,
     element::LoadedType,
     util::{
-        etag::{etag_check, etag_hash},
-        extract::ExtractInfo,
         AppState,
+        compress::{Encoding, candidates, is_compressible, negotiate},
+        content_cache::ContentCache,
+        etag::conditional_check,
+        extract::ExtractInfo,
+        mfs::{FileMeta, FileNode},
+        range::{RangeSpec, file_range_response, range_response, requested_range},
     },
 };
 
@@ -64,40 +68,42 @@ async fn handle_play_index(
         Err(resp) => return resp,
     };
 
-    fn read_html(path: &PathBuf, headers: &HeaderMap) -> Response {
-        match fs::read(path) {
-            Ok(html) => {
-                if let Some(resp) = etag_check(&html, &headers) {
-                    return resp;
-                }
-
-                let etag_val = etag_hash(&html);
-                (
-                    StatusCode::OK,
-                    [
-                        (CONTENT_TYPE, "text/html; charset=utf-8"),
-                        (CACHE_CONTROL, CACHE_HEADER),
-                        (ETAG, etag_val.as_str()),
-                    ],
-                    Html(String::from_utf8_lossy(&html).to_string()),
-                )
-                    .into_response()
-            }
-            Err(err) => {
-                error!("Failed to read html file: {err}");
-                (
+    let cache_control = info.cache_policy().for_index();
+
+    fn read_html(
+        path: &std::path::Path,
+        cache: &ContentCache,
+        cache_control: &str,
+        headers: &HeaderMap,
+    ) -> Response {
+        let node = match FileNode::from_path(path) {
+            Some(node) => node,
+            None => {
+                error!("Failed to read html file: {}", path.display());
+                return (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Failed to read enter file: {err}"),
+                    format!("Failed to read enter file: {}", path.display()),
                 )
-                    .into_response()
+                    .into_response();
             }
-        }
+        };
+
+        serve_file(
+            &node,
+            "text/html; charset=utf-8",
+            |encoding| FileNode::from_path(&sibling_path(path, encoding)),
+            cache,
+            cache_control,
+            headers,
+        )
     }
 
-    match info {
-        LoadedType::Plain { enter_path, .. } => read_html(&enter_path, &headers),
+    match &*info {
+        LoadedType::Plain { enter_path, .. } => {
+            read_html(enter_path, state.content_cache(), cache_control, &headers)
+        }
         LoadedType::SugarCube { info, .. } => {
-            let instance = match info.get_instance(&instance_id) {
+            let instance = match info.get_playable(&instance_id) {
                 Some(instance) => instance,
                 None => {
                     warn!("Instance ID {instance_id} in {manage_id} not found");
@@ -108,7 +114,12 @@ async fn handle_play_index(
                         .into_response();
                 }
             };
-            read_html(&instance.index_path, &headers)
+            read_html(
+                &instance.index_path,
+                state.content_cache(),
+                cache_control,
+                &headers,
+            )
         }
     }
 }
@@ -139,75 +150,33 @@ async fn handle_other_path(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let loaded_type = match state.extract_info(&manage_id) {
+    // Reading files blocks, and encoding a large one on the fly takes a while
+    tokio::task::spawn_blocking(move || {
+        serve_other_path(&manage_id, &instance_id, &other_path, &state, &headers)
+    })
+    .await
+    .unwrap_or_else(|err| {
+        error!("Serving task panicked: {err}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
+
+fn serve_other_path(
+    manage_id: &str,
+    instance_id: &str,
+    other_path: &str,
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Response {
+    let loaded_type = match state.extract_info(manage_id) {
         Ok(info) => info,
         Err(resp) => return resp,
     };
 
-    fn read_file(path: &PathBuf, headers: &HeaderMap, manage_id: &str) -> Response {
-        if !path.exists() || path.is_dir() {
-            warn!("File not found for '{}': {}", manage_id, path.display());
-            return (
-                StatusCode::NOT_FOUND,
-                format!("File not found for '{}': {}", manage_id, path.display()),
-            )
-                .into_response();
-        }
+    let cache_control = loaded_type.cache_policy().for_path(other_path);
+    let mime = mime_guess::from_path(other_path).first_or_octet_stream();
 
-        match fs::metadata(&path) {
-            Ok(metadata) => {
-                const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
-                if metadata.len() > MAX_FILE_SIZE {
-                    error!(
-                        "File size exceeds limit: {} bytes, path: {:?}",
-                        metadata.len(),
-                        path
-                    );
-                    return (
-                        StatusCode::BAD_REQUEST,
-                        format!(
-                            "The file size exceeds the limit of {} MB",
-                            MAX_FILE_SIZE / 1024 / 1024
-                        ),
-                    )
-                        .into_response();
-                }
-            }
-            Err(err) => {
-                error!("Failed to get metadata: {}, path: {:?}", err, path);
-            }
-        }
-
-        let content = match fs::read(path) {
-            Ok(content) => content,
-            Err(err) => {
-                error!("Failed to read file: {}, path: {:?}", err, path);
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Failed to read file for '{manage_id}': {err}"),
-                )
-                    .into_response();
-            }
-        };
-        if let Some(resp) = etag_check(&content, headers) {
-            return resp;
-        }
-
-        let mime = mime_guess::from_path(&path).first_or_octet_stream();
-        let etag_val = etag_hash(&content);
-        (
-            StatusCode::OK,
-            [
-                (CONTENT_TYPE, mime.as_ref()),
-                (CACHE_CONTROL, CACHE_HEADER),
-                (ETAG, etag_val.as_str()),
-            ],
-            content,
-        )
-            .into_response()
-    }
-
-    match loaded_type {
+    match &*loaded_type {
         LoadedType::Plain { root_path, .. } => {
             let mut actual_path = root_path.clone();
 
@@ -226,10 +195,57 @@ async fn handle_other_path(
                 actual_path.push(component);
             }
 
-            read_file(&actual_path, &headers, &manage_id)
+            let node = match FileNode::from_path(&actual_path) {
+                Some(node) => node,
+                None => {
+                    warn!(
+                        "File not found for '{}': {}",
+                        manage_id,
+                        actual_path.display()
+                    );
+                    return (
+                        StatusCode::NOT_FOUND,
+                        format!(
+                            "File not found for '{}': {}",
+                            manage_id,
+                            actual_path.display()
+                        ),
+                    )
+                        .into_response();
+                }
+            };
+
+            // Ranges are read in parts, so seeking in large media stays possible
+            const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+            if node.meta().size > MAX_FILE_SIZE
+                && requested_range(headers, node.meta(), node.meta().size) == RangeSpec::Full
+            {
+                error!(
+                    "File size exceeds limit: {} bytes, path: {:?}",
+                    node.meta().size,
+                    actual_path
+                );
+                return (
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "The file size exceeds the limit of {} MB",
+                        MAX_FILE_SIZE / 1024 / 1024
+                    ),
+                )
+                    .into_response();
+            }
+
+            serve_file(
+                &node,
+                mime.as_ref(),
+                |encoding| FileNode::from_path(&sibling_path(&actual_path, encoding)),
+                state.content_cache(),
+                cache_control,
+                headers,
+            )
         }
         LoadedType::SugarCube { info, .. } => {
-            let instance = match info.get_instance(&instance_id) {
+            let instance = match info.get_playable(instance_id) {
                 Some(instance) => instance,
                 None => {
                     warn!("Instance ID {instance_id} in {manage_id} not found");
@@ -240,7 +256,7 @@ async fn handle_other_path(
                         .into_response();
                 }
             };
-            let actual_node = match instance.layer_merged.get(&other_path) {
+            let actual_node = match instance.layer_merged.get(other_path) {
                 Some(path) => path,
                 None => {
                     warn!("Path '{other_path}' not found in instance {instance_id}");
@@ -252,37 +268,126 @@ async fn handle_other_path(
                 }
             };
 
-            let (content, file_name) = match actual_node.resolve() {
-                Some(content) => content,
-                None => {
-                    warn!("Failed to resolve path '{other_path}' in instance {instance_id}");
-                    return (
-                        StatusCode::NOT_FOUND,
-                        format!("Failed to resolve path '{other_path}' in instance {instance_id}"),
-                    )
-                        .into_response();
-                }
-            };
-            if let Some(resp) = etag_check(&content, &headers) {
-                return resp;
-            }
-
-            let mime = mime_guess::from_path(file_name).first_or_octet_stream();
-            let etag_val = etag_hash(&content);
-            (
-                StatusCode::OK,
-                [
-                    (CONTENT_TYPE, mime.as_ref()),
-                    (CACHE_CONTROL, CACHE_HEADER),
-                    (ETAG, etag_val.as_str()),
-                ],
-                content,
+            serve_file(
+                actual_node,
+                mime.as_ref(),
+                |encoding| {
+                    instance
+                        .layer_merged
+                        .get(&format!("{other_path}{}", encoding.suffix()))
+                        .cloned()
+                },
+                state.content_cache(),
+                cache_control,
+                headers,
             )
-                .into_response()
         }
     }
 }
 
+/// Path of the precompressed sibling of a file, e.g. `app.js.br`
+fn sibling_path(path: &std::path::Path, encoding: Encoding) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(encoding.suffix());
+    PathBuf::from(name)
+}
+
+/// Serve a file node with conditional requests, content coding and ranges.
+///
+/// `sibling` looks up precompressed variants of the same file, which are
+/// preferred over compressing on the fly.
+fn serve_file(
+    node: &FileNode,
+    mime: &str,
+    sibling: impl Fn(Encoding) -> Option<FileNode>,
+    cache: &ContentCache,
+    cache_control: &str,
+    headers: &HeaderMap,
+) -> Response {
+    let candidates = candidates(mime, node.meta().size, headers);
+    let precompressed = candidates
+        .iter()
+        .filter_map(|encoding| sibling(*encoding).map(|node| (*encoding, node)))
+        .collect::<Vec<_>>();
+    let available = precompressed.iter().map(|(e, _)| *e).collect::<Vec<_>>();
+
+    // Ranges of a plain file are read straight from disk, so they are served as is
+    let ranged = matches!(node, FileNode::File { .. }) && headers.contains_key(RANGE);
+    let (encoding, source) = match negotiate(headers, &available) {
+        _ if ranged => (Encoding::Identity, None),
+        Encoding::Identity => (negotiate(headers, candidates), None),
+        encoding => (
+            encoding,
+            precompressed
+                .iter()
+                .find(|(e, _)| *e == encoding)
+                .map(|(_, node)| node),
+        ),
+    };
+    // The body is read live, so the validators must describe the live file
+    let scanned = source.unwrap_or(node);
+    let Some(file) = scanned.current() else {
+        warn!("File vanished since scanning: {}", scanned.cache_key());
+        return (StatusCode::NOT_FOUND, "Failed to resolve file").into_response();
+    };
+    let meta = FileMeta {
+        etag: encoding.variant_etag(&file.meta().etag),
+        ..file.meta().clone()
+    };
+
+    let mut resp = if let Some(resp) = conditional_check(&meta, cache_control, headers) {
+        resp
+    } else if ranged
+        && let FileNode::File { path, .. } = &*file
+        && let Some(resp) = file_range_response(
+            std::path::Path::new(path),
+            mime,
+            &meta,
+            cache_control,
+            headers,
+        )
+    {
+        resp
+    } else {
+        let modified = meta.modified;
+
+        let content = if source.is_none() && encoding != Encoding::Identity {
+            // Encoded variants are cached next to the identity content
+            let key = format!("{}#{}", file.cache_key(), encoding.token());
+            cache.get_or_load(&key, modified, || {
+                let content = file.load(cache)?;
+                encoding
+                    .encode(&content)
+                    .map_err(|err| {
+                        error!("Failed to encode content as {}: {err}", encoding.token())
+                    })
+                    .ok()
+                    .map(Bytes::from)
+            })
+        } else {
+            file.load(cache)
+        };
+        let Some(content) = content else {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read file: {}", file.cache_key()),
+            )
+                .into_response();
+        };
+        range_response(content, mime, &meta, cache_control, headers)
+    };
+
+    if encoding != Encoding::Identity && resp.status().is_success() {
+        resp.headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.token()));
+    }
+    if is_compressible(mime) {
+        resp.headers_mut()
+            .insert(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    resp
+}
+
 fn to_save_path(manage_id: &str, instance_id: &str) -> PathBuf {
     let mut data_dir = config_ref().data_dir();
     data_dir.push(manage_id);
@@ -291,12 +396,13 @@ fn to_save_path(manage_id: &str, instance_id: &str) -> PathBuf {
     data_dir
 }
 
+#[allow(clippy::result_large_err)]
 fn check_save_func(
     manage_id: &str,
     instance_id: &str,
     state: &Arc<AppState>,
 ) -> Result<PathBuf, Response> {
-    let info = match state.extract_sc_info(&manage_id) {
+    let info = match state.extract_sc_info(manage_id) {
         Ok(info) => info,
         Err(resp) => {
             warn!("Failed to extract SC info for {manage_id}: {instance_id}");
@@ -310,7 +416,7 @@ fn check_save_func(
         )
             .into_response());
     }
-    if let Some(resp) = info.check_instance(&instance_id) {
+    if let Some(resp) = info.check_instance(instance_id) {
         return Err(resp);
     }
 
@@ -1,24 +1,26 @@
-use std::{fs, sync::Arc};
+use std::sync::Arc;
 
 use axum::{
-    extract::{Path, State},
+    Router,
+    extract::{Path, Query, State},
     http::{
-        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH}, HeaderMap,
-        StatusCode,
+        HeaderMap, StatusCode,
+        header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, LAST_MODIFIED},
     },
     response::IntoResponse,
     routing::get,
-    Router,
 };
-use lazy_static::lazy_static;
+use serde::Deserialize;
 use tracing::{error, info};
 
 use crate::{
-    constants::{CACHE_HEADER, SSI_MOD_ID},
+    constants::{SSI_MOD_ETAG, SSI_MOD_ID, SSI_MOD_INTERNAL},
     util::{
-        etag::{etag_check, etag_hash},
-        extract::ExtractInfo,
         AppState,
+        cache_policy::version_token,
+        etag::{conditional_check, etag_match, http_date},
+        extract::ExtractInfo,
+        mfs::FileNode,
     },
 };
 
@@ -29,13 +31,15 @@ pub(super) fn routes() -> Router<Arc<AppState>> {
     )
 }
 
-const SSI_MOD_INTERNAL: &[u8] = include_bytes!("../../../resources/save-sync-integration.mod.zip");
-lazy_static! {
-    static ref SAVE_SYNC_INTEGRATION_ETAG: String = etag_hash(SSI_MOD_INTERNAL);
+#[derive(Debug, Deserialize)]
+struct ModQuery {
+    /// Version token, see [version_token]
+    v: Option<String>,
 }
 
 async fn handle_sc_mods(
     Path((manage_id, mod_id, mod_sub_id)): Path<(String, String, String)>,
+    Query(query): Query<ModQuery>,
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
@@ -43,6 +47,7 @@ async fn handle_sc_mods(
         Ok(info) => info,
         Err(res) => return res,
     };
+    let policy = game_info.loaded().cache_policy();
 
     if !game_info.use_mods {
         return (
@@ -55,45 +60,32 @@ async fn handle_sc_mods(
     if mod_id == SSI_MOD_ID {
         info!("Responding to SSI Mod Request");
 
-        if let Some(if_not_match) = headers.get(IF_NONE_MATCH) {
-            if let Ok(cli_tag) = if_not_match.to_str() {
-                if cli_tag == SAVE_SYNC_INTEGRATION_ETAG.as_str() {
-                    return (
-                        StatusCode::NOT_MODIFIED,
-                        [
-                            (CACHE_CONTROL, CACHE_HEADER),
-                            (ETAG, SAVE_SYNC_INTEGRATION_ETAG.as_str()),
-                        ],
-                    )
-                        .into_response();
-                }
-            }
+        let cache_control = policy.for_mod(query.v.as_deref(), version_token(&SSI_MOD_ETAG));
+        if let Some(resp) = etag_match(&SSI_MOD_ETAG, cache_control, &headers) {
+            return resp;
         }
 
         return (
             StatusCode::OK,
             [
                 (CONTENT_TYPE, "application/zip"),
-                (CACHE_CONTROL, CACHE_HEADER),
-                (ETAG, &SAVE_SYNC_INTEGRATION_ETAG),
+                (CACHE_CONTROL, cache_control),
+                (ETAG, &SSI_MOD_ETAG),
             ],
             SSI_MOD_INTERNAL,
         )
             .into_response();
     }
 
-    let mod_data = match game_info.get_mod(&mod_id, &mod_sub_id) {
-        Some(path) => match fs::read(path) {
-            Ok(data) => data,
-            Err(err) => {
-                error!("Failed to read mod file: {err}");
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Failed to read mod file: {err}"),
-                )
-                    .into_response();
-            }
-        },
+    let mod_path = match game_info.get_mod(&mod_id, &mod_sub_id) {
+        Some(entry) if let Some(error) = &entry.error => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Mod ID {mod_id}:{mod_sub_id} is broken: {error}"),
+            )
+                .into_response();
+        }
+        Some(entry) => &entry.path,
         None => {
             return (
                 StatusCode::NOT_FOUND,
@@ -104,17 +96,42 @@ async fn handle_sc_mods(
     };
     info!("Responding to Mod ID: {mod_id}:{mod_sub_id}");
 
-    if let Some(resp) = etag_check(&mod_data, &headers) {
+    let node = match FileNode::from_path(mod_path) {
+        Some(node) => node,
+        None => {
+            error!("Failed to read mod file: {}", mod_path.display());
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read mod file: {mod_id}:{mod_sub_id}"),
+            )
+                .into_response();
+        }
+    };
+    let meta = node.meta();
+    let cache_control = policy.for_mod(query.v.as_deref(), version_token(&meta.etag));
+    if let Some(resp) = conditional_check(meta, cache_control, &headers) {
         return resp;
     }
 
-    let etag_val = etag_hash(&mod_data);
+    let mod_data = match node.load(state.content_cache()) {
+        Some(data) => data,
+        None => {
+            error!("Failed to read mod file: {}", mod_path.display());
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read mod file: {mod_id}:{mod_sub_id}"),
+            )
+                .into_response();
+        }
+    };
+
     (
         StatusCode::OK,
         [
             (CONTENT_TYPE, "application/zip"),
-            (CACHE_CONTROL, CACHE_HEADER),
-            (ETAG, etag_val.as_str()),
+            (CACHE_CONTROL, cache_control),
+            (ETAG, meta.etag.as_str()),
+            (LAST_MODIFIED, http_date(meta.modified).as_str()),
         ],
         mod_data,
     )
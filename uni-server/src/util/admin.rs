@@ -0,0 +1,48 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{StatusCode, header::AUTHORIZATION, request::Parts},
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use super::config::{ReadConfig, config_ref};
+
+/// Extractor guarding the management API,
+/// requires `Authorization: Bearer <admin_token>`
+pub struct Admin;
+
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Response> {
+        let Some(token) = config_ref().admin_token() else {
+            return Err((
+                StatusCode::FORBIDDEN,
+                "Management API is disabled, set 'admin_token' to enable it",
+            )
+                .into_response());
+        };
+
+        let provided = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        match provided {
+            Some(provided) if token_eq(provided.trim(), token) => Ok(Admin),
+            _ => {
+                warn!("Rejected management request to {}", parts.uri.path());
+                Err((StatusCode::UNAUTHORIZED, "Invalid or missing admin token").into_response())
+            }
+        }
+    }
+}
+
+/// Compare without returning early on the first differing byte
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -0,0 +1,177 @@
+use std::fmt::{self, Display};
+
+use bincode::{
+    config::standard,
+    error::{DecodeError, EncodeError},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use xxhash_rust::xxh3::xxh3_64;
+
+const MAGIC: &[u8; 4] = b"UNRC";
+const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Why a cache file was rejected
+#[derive(Debug)]
+pub enum CacheError {
+    Magic,
+    Kind { found: String, expected: String },
+    FormatVersion { found: u16, expected: u16 },
+    ServerVersion { found: String, expected: String },
+    Checksum,
+    Truncated,
+    Decode(DecodeError),
+}
+
+impl Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Magic => write!(f, "not a cache file (bad magic)"),
+            CacheError::Kind { found, expected } => {
+                write!(f, "cache kind is '{found}', expected '{expected}'")
+            }
+            CacheError::FormatVersion { found, expected } => {
+                write!(f, "format version is {found}, expected {expected}")
+            }
+            CacheError::ServerVersion { found, expected } => {
+                write!(f, "written by server {found}, current is {expected}")
+            }
+            CacheError::Checksum => write!(f, "checksum mismatch, file is corrupted"),
+            CacheError::Truncated => write!(f, "header is truncated"),
+            CacheError::Decode(e) => write!(f, "payload decode failed: {e}"),
+        }
+    }
+}
+
+/// Layout: magic, kind, format version, server version, checksum, payload.
+///
+/// `kind` tells different caches apart, `version` is bumped
+/// whenever the payload type changes.
+pub fn encode_cache<T: Serialize>(
+    kind: &str,
+    version: u16,
+    payload: &T,
+) -> Result<Vec<u8>, EncodeError> {
+    let body = bincode::serde::encode_to_vec(payload, standard())?;
+
+    let mut out = Vec::with_capacity(body.len() + 32);
+    out.extend_from_slice(MAGIC);
+    push_str(&mut out, kind);
+    out.extend_from_slice(&version.to_le_bytes());
+    push_str(&mut out, SERVER_VERSION);
+    out.extend_from_slice(&xxh3_64(&body).to_le_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+/// Decode a file written by [encode_cache], checking every header field
+pub fn decode_cache<T: DeserializeOwned>(
+    kind: &str,
+    version: u16,
+    data: &[u8],
+) -> Result<T, CacheError> {
+    let mut reader = Reader(data);
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(CacheError::Magic);
+    }
+    let found_kind = reader.string()?;
+    if found_kind != kind {
+        return Err(CacheError::Kind {
+            found: found_kind,
+            expected: kind.to_string(),
+        });
+    }
+    let found_version = u16::from_le_bytes(reader.array()?);
+    if found_version != version {
+        return Err(CacheError::FormatVersion {
+            found: found_version,
+            expected: version,
+        });
+    }
+    let found_server = reader.string()?;
+    if found_server != SERVER_VERSION {
+        return Err(CacheError::ServerVersion {
+            found: found_server,
+            expected: SERVER_VERSION.to_string(),
+        });
+    }
+    let checksum = u64::from_le_bytes(reader.array()?);
+    let body = reader.0;
+    if xxh3_64(body) != checksum {
+        return Err(CacheError::Checksum);
+    }
+
+    bincode::serde::decode_from_slice(body, standard())
+        .map(|(payload, _)| payload)
+        .map_err(CacheError::Decode)
+}
+
+fn push_str(out: &mut Vec<u8>, s: &str) {
+    let len = s.len().min(u8::MAX as usize);
+    out.push(len as u8);
+    out.extend_from_slice(&s.as_bytes()[..len]);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CacheError> {
+        if self.0.len() < n {
+            return Err(CacheError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CacheError> {
+        Ok(self.take(N)?.try_into().expect("length checked"))
+    }
+
+    fn string(&mut self) -> Result<String, CacheError> {
+        let [len] = self.array::<1>()?;
+        Ok(String::from_utf8_lossy(self.take(len as usize)?).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cache_header() {
+        let payload = vec!["a".to_string(), "b".to_string()];
+        let mut data = encode_cache("test", 1, &payload).unwrap();
+        assert_eq!(
+            decode_cache::<Vec<String>>("test", 1, &data).unwrap(),
+            payload
+        );
+
+        assert!(matches!(
+            decode_cache::<Vec<String>>("test", 2, &data),
+            Err(CacheError::FormatVersion {
+                found: 1,
+                expected: 2
+            })
+        ));
+        assert!(matches!(
+            decode_cache::<Vec<String>>("other", 1, &data),
+            Err(CacheError::Kind { .. })
+        ));
+        assert!(matches!(
+            decode_cache::<Vec<String>>("test", 1, &data[..6]),
+            Err(CacheError::Truncated)
+        ));
+
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        assert!(matches!(
+            decode_cache::<Vec<String>>("test", 1, &data),
+            Err(CacheError::Checksum)
+        ));
+        assert!(matches!(
+            decode_cache::<Vec<String>>("test", 1, b"not a cache"),
+            Err(CacheError::Magic)
+        ));
+    }
+}
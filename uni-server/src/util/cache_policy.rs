@@ -0,0 +1,78 @@
+use anyhow::Result;
+use globset::{Glob, GlobMatcher};
+
+use super::config::CacheConfig;
+use crate::constants::CACHE_HEADER;
+
+/// Policy for URLs carrying a version token matching the current content
+pub const IMMUTABLE_HEADER: &str = "public, max-age=31536000, immutable";
+/// Policy for files that change in place and must be revalidated
+pub const REVALIDATE_HEADER: &str = "no-cache";
+
+/// Compiled [CacheConfig] of a manage
+#[derive(Debug)]
+pub struct CachePolicy {
+    default: String,
+    index: String,
+    mods: String,
+    rules: Vec<(GlobMatcher, String)>,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            default: CACHE_HEADER.to_string(),
+            index: REVALIDATE_HEADER.to_string(),
+            mods: REVALIDATE_HEADER.to_string(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl CachePolicy {
+    pub fn new(config: &CacheConfig) -> Result<Self> {
+        let base = Self::default();
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok((
+                    Glob::new(&rule.pattern)?.compile_matcher(),
+                    rule.control.clone(),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            default: config.default.clone().unwrap_or(base.default),
+            index: config.index.clone().unwrap_or(base.index),
+            mods: config.mods.clone().unwrap_or(base.mods),
+            rules,
+        })
+    }
+
+    /// Policy for a requested path, `*` in patterns also matches `/`
+    pub fn for_path(&self, path: &str) -> &str {
+        self.rules
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(path))
+            .map_or(self.default.as_str(), |(_, control)| control.as_str())
+    }
+
+    pub fn for_index(&self) -> &str {
+        &self.index
+    }
+
+    /// Policy for a mod zip, immutable when requested with its current version token
+    pub fn for_mod(&self, requested: Option<&str>, current: &str) -> &str {
+        match requested {
+            Some(token) if token == current => IMMUTABLE_HEADER,
+            _ => &self.mods,
+        }
+    }
+}
+
+/// URL-safe version token derived from an ETag, used in content-hashed URLs
+pub fn version_token(etag: &str) -> &str {
+    etag.trim_start_matches("W/").trim_matches('"')
+}
@@ -0,0 +1,178 @@
+use std::io::Write;
+
+use anyhow::Result;
+use axum::http::{
+    HeaderMap,
+    header::{ACCEPT_ENCODING, RANGE},
+};
+use flate2::{Compression, write::GzEncoder};
+
+/// Bodies smaller than this are not worth compressing
+const MIN_COMPRESS_SIZE: u64 = 1024;
+
+/// Content codings supported by the server, in order of preference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+    Identity,
+}
+
+impl Encoding {
+    pub const COMPRESSED: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    /// Token used in `Accept-Encoding` and `Content-Encoding`
+    pub fn token(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+            Encoding::Identity => "identity",
+        }
+    }
+
+    /// File suffix of a precompressed sibling
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => ".br",
+            Encoding::Zstd => ".zst",
+            Encoding::Gzip => ".gz",
+            Encoding::Identity => "",
+        }
+    }
+
+    /// Distinguish the ETag of an encoded representation from the identity one
+    pub fn variant_etag(&self, etag: &str) -> String {
+        match self {
+            Encoding::Identity => etag.to_string(),
+            _ => {
+                let (prefix, opaque) = match etag.strip_prefix("W/") {
+                    Some(opaque) => ("W/", opaque),
+                    None => ("", etag),
+                };
+                format!("{prefix}\"{}-{}\"", opaque.trim_matches('"'), self.token())
+            }
+        }
+    }
+
+    /// Compress the body on the fly, levels favour speed over ratio
+    pub fn encode(&self, content: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut out = Vec::with_capacity(content.len() / 4);
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                    writer.write_all(content)?;
+                }
+                Ok(out)
+            }
+            Encoding::Zstd => Ok(zstd::encode_all(content, 3)?),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(content)?;
+                Ok(encoder.finish()?)
+            }
+            Encoding::Identity => Ok(content.to_vec()),
+        }
+    }
+}
+
+/// Whether the MIME type benefits from compression
+pub fn is_compressible(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence,
+            "application/javascript"
+                | "application/x-javascript"
+                | "application/json"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/x-icon"
+        )
+}
+
+/// Pick the best encoding accepted by the client among `available`.
+///
+/// Parses q-values and `*`, identity is returned when nothing fits.
+pub fn negotiate(headers: &HeaderMap, available: &[Encoding]) -> Encoding {
+    let mut accepted: Vec<(&str, f32)> = Vec::new();
+    for value in headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+    {
+        for item in value.split(',') {
+            let mut parts = item.split(';');
+            let token = parts.next().unwrap_or_default().trim();
+            if token.is_empty() {
+                continue;
+            }
+            let q = parts
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            accepted.push((token, q));
+        }
+    }
+
+    let quality = |encoding: &Encoding| {
+        let token = encoding.token();
+        accepted
+            .iter()
+            .find(|(t, _)| t.eq_ignore_ascii_case(token))
+            .or_else(|| accepted.iter().find(|(t, _)| *t == "*"))
+            .map_or(0.0, |(_, q)| *q)
+    };
+
+    let mut best = (Encoding::Identity, 0.0);
+    for encoding in Encoding::COMPRESSED {
+        if !available.contains(&encoding) {
+            continue;
+        }
+        let q = quality(&encoding);
+        if q > best.1 {
+            best = (encoding, q);
+        }
+    }
+    best.0
+}
+
+/// Encodings worth negotiating for a body, empty when it should be sent as is
+pub fn candidates(mime: &str, len: u64, headers: &HeaderMap) -> &'static [Encoding] {
+    // Ranges apply to the selected representation, keep them on identity
+    if headers.contains_key(RANGE) || len < MIN_COMPRESS_SIZE || !is_compressible(mime) {
+        &[]
+    } else {
+        &Encoding::COMPRESSED
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, "gzip, deflate, br".parse().unwrap());
+        assert_eq!(negotiate(&headers, &Encoding::COMPRESSED), Encoding::Brotli);
+        assert_eq!(negotiate(&headers, &[Encoding::Gzip]), Encoding::Gzip);
+
+        headers.insert(ACCEPT_ENCODING, "br;q=0.5, zstd;q=0.8".parse().unwrap());
+        assert_eq!(negotiate(&headers, &Encoding::COMPRESSED), Encoding::Zstd);
+
+        headers.insert(ACCEPT_ENCODING, "*;q=0, gzip".parse().unwrap());
+        assert_eq!(negotiate(&headers, &Encoding::COMPRESSED), Encoding::Gzip);
+
+        headers.insert(ACCEPT_ENCODING, "identity".parse().unwrap());
+        assert_eq!(
+            negotiate(&headers, &Encoding::COMPRESSED),
+            Encoding::Identity
+        );
+    }
+}
@@ -13,11 +13,18 @@ pub struct Config {
     root: String,
     #[serde(default)]
     manage: HashMap<String, ManageInfo>,
+    #[serde(default)]
+    content_cache: ContentCacheConfig,
+    /// Bearer token for the management API, which is disabled when unset
+    #[serde(default)]
+    admin_token: Option<String>,
 }
 
 pub trait ReadConfig {
     fn port(&self) -> u16;
     fn data_dir(&self) -> PathBuf;
+    fn content_cache(&self) -> &ContentCacheConfig;
+    fn admin_token(&self) -> Option<&str>;
     fn manage_iter(&self) -> impl Iterator<Item = (&String, &ManageInfo)>;
     fn manage_size(&self) -> usize;
     fn manage_empty(&self) -> bool {
@@ -31,6 +38,8 @@ impl Default for Config {
             port: 3500,
             root: String::from("data"),
             manage: HashMap::new(),
+            content_cache: ContentCacheConfig::default(),
+            admin_token: None,
         }
     }
 }
@@ -44,6 +53,14 @@ impl ReadConfig for Config {
         cd_in(&self.root)
     }
 
+    fn content_cache(&self) -> &ContentCacheConfig {
+        &self.content_cache
+    }
+
+    fn admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref().filter(|t| !t.is_empty())
+    }
+
     fn manage_iter(&self) -> impl Iterator<Item = (&String, &ManageInfo)> {
         self.manage.iter()
     }
@@ -65,7 +82,7 @@ pub fn config_ref() -> &'static Config {
                 if err.kind() == std::io::ErrorKind::NotFound {
                     let default = Config::default();
                     let default_content = toml::to_string_pretty(&default)?;
-                    fs::create_dir_all(&config_path.parent().unwrap_or(&config_path))?;
+                    fs::create_dir_all(config_path.parent().unwrap_or(&config_path))?;
                     fs::write(&config_path, default_content)?;
                     info!(
                         "Config file not found, created default config file at: {}",
@@ -86,12 +103,79 @@ pub fn config_ref() -> &'static Config {
     CONFIG.get_or_init(|| load().expect("Cannot load config file at all!"))
 }
 
+/// In-memory cache of served file contents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentCacheConfig {
+    /// Total budget in bytes, `0` disables the cache
+    pub max_bytes: u64,
+    /// Files larger than this are always read from disk
+    pub max_entry_bytes: u64,
+}
+
+impl Default for ContentCacheConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 256 * 1024 * 1024,
+            max_entry_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct ManageInfo {
     #[serde(default)]
     pub name: Option<String>,
     pub mode: ManageType,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub lookup: LookupConfig,
+}
+
+/// Fallback normalization of requested paths in SugarCube layers,
+/// applied when no file matches exactly
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LookupConfig {
+    /// Compare paths ignoring case
+    pub case_insensitive: bool,
+    /// Compare paths in Unicode NFC form
+    pub unicode_nfc: bool,
+    /// Decode `%XX` sequences left in paths and file names
+    pub percent_decode: bool,
+}
+
+impl LookupConfig {
+    pub fn enabled(&self) -> bool {
+        self.case_insensitive || self.unicode_nfc || self.percent_decode
+    }
+}
+
+/// `Cache-Control` values for a manage, unset entries use built-in defaults
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Files not matched by any rule
+    #[serde(default)]
+    pub default: Option<String>,
+    /// The `index-path` entry HTML
+    #[serde(default)]
+    pub index: Option<String>,
+    /// Mod zips requested without a version token
+    #[serde(default)]
+    pub mods: Option<String>,
+    /// Glob rules on the requested path, first match wins
+    #[serde(default)]
+    pub rules: Vec<CacheRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheRule {
+    pub pattern: String,
+    pub control: String,
 }
 
 fn default_enter_path() -> String {
@@ -112,9 +196,36 @@ pub enum ManageType {
         use_mods: bool,
         #[serde(default)]
         use_save_sync: bool,
+        /// Where the save-sync mod goes in `modList.json`, unless an instance
+        /// lists it among its mods to place it explicitly
+        #[serde(default)]
+        save_sync_position: SaveSyncPosition,
+        #[serde(default)]
+        verify_mods: VerifyMods,
     },
 }
 
+/// How mod zips are checked for corruption at load time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerifyMods {
+    /// Only parse `boot.json`
+    Off,
+    /// Verify CRCs when a zip is new or changed
+    #[default]
+    Cached,
+    /// Verify CRCs of every zip on each load
+    Always,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SaveSyncPosition {
+    First,
+    #[default]
+    Last,
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -127,6 +238,8 @@ mod test {
         let info1 = ManageInfo {
             name: Some("Test".to_string()),
             mode: manage_type.clone(),
+            cache: CacheConfig::default(),
+            lookup: LookupConfig::default(),
         };
         let ser1 = toml::to_string_pretty(&info1).unwrap();
         println!("ser1: ");
@@ -135,10 +248,24 @@ mod test {
         let manage_type = ManageType::SugarCube {
             use_mods: true,
             use_save_sync: false,
+            save_sync_position: SaveSyncPosition::First,
+            verify_mods: VerifyMods::Always,
         };
         let info2 = ManageInfo {
             name: Some("Test2".to_string()),
             mode: manage_type.clone(),
+            cache: CacheConfig {
+                index: Some("no-cache".to_string()),
+                rules: vec![CacheRule {
+                    pattern: "img/**".to_string(),
+                    control: "public, max-age=31536000, immutable".to_string(),
+                }],
+                ..Default::default()
+            },
+            lookup: LookupConfig {
+                case_insensitive: true,
+                ..Default::default()
+            },
         };
         let ser2 = toml::to_string_pretty(&info2).unwrap();
         println!("ser2: ");
@@ -0,0 +1,166 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::SystemTime,
+};
+
+use axum::body::Bytes;
+use lru::LruCache;
+use serde::Serialize;
+
+use super::config::ContentCacheConfig;
+
+/// Bounded LRU cache of file contents, keyed by resolved path
+/// and validated by modification time
+#[derive(Debug)]
+pub struct ContentCache {
+    max_bytes: u64,
+    max_entry_bytes: u64,
+    inner: Mutex<CacheInner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug)]
+struct CacheInner {
+    entries: LruCache<String, CacheEntry>,
+    bytes: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    modified: SystemTime,
+    data: Bytes,
+}
+
+/// Snapshot of the cache counters, for monitoring
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    pub max_entry_bytes: u64,
+}
+
+impl ContentCache {
+    pub fn new(config: &ContentCacheConfig) -> Self {
+        Self {
+            max_bytes: config.max_bytes,
+            max_entry_bytes: config.max_entry_bytes.min(config.max_bytes),
+            inner: Mutex::new(CacheInner {
+                entries: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// Get the cached data of `key` if it was stored with the same `modified`,
+    /// otherwise call `load` and store its result when it fits.
+    pub fn get_or_load(
+        &self,
+        key: &str,
+        modified: SystemTime,
+        load: impl FnOnce() -> Option<Bytes>,
+    ) -> Option<Bytes> {
+        if !self.enabled() {
+            return load();
+        }
+
+        if let Ok(mut inner) = self.inner.lock()
+            && let Some(entry) = inner.entries.get(key)
+        {
+            if entry.modified == modified {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(entry.data.clone());
+            }
+            // Stale, drop it so the budget is not held by outdated data
+            if let Some(stale) = inner.entries.pop(key) {
+                inner.bytes -= stale.data.len() as u64;
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let data = load()?;
+        let size = data.len() as u64;
+        if size <= self.max_entry_bytes
+            && let Ok(mut inner) = self.inner.lock()
+        {
+            if let Some(replaced) = inner.entries.put(
+                key.to_string(),
+                CacheEntry {
+                    modified,
+                    data: data.clone(),
+                },
+            ) {
+                inner.bytes -= replaced.data.len() as u64;
+            }
+            inner.bytes += size;
+            while inner.bytes > self.max_bytes {
+                match inner.entries.pop_lru() {
+                    Some((_, evicted)) => inner.bytes -= evicted.data.len() as u64,
+                    None => break,
+                }
+            }
+        }
+
+        Some(data)
+    }
+
+    pub fn stats(&self) -> ContentCacheStats {
+        let (entries, bytes) = self
+            .inner
+            .lock()
+            .map(|inner| (inner.entries.len(), inner.bytes))
+            .unwrap_or_default();
+        ContentCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries,
+            bytes,
+            max_bytes: self.max_bytes,
+            max_entry_bytes: self.max_entry_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_eviction() {
+        let cache = ContentCache::new(&ContentCacheConfig {
+            max_bytes: 10,
+            max_entry_bytes: 6,
+        });
+        let now = SystemTime::now();
+        let load = |n: usize| move || Some(Bytes::from(vec![0u8; n]));
+
+        cache.get_or_load("a", now, load(4));
+        cache.get_or_load("b", now, load(4));
+        cache.get_or_load("a", now, load(4));
+        cache.get_or_load("c", now, load(4));
+        cache.get_or_load("d", now, load(8));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 4));
+        assert_eq!((stats.entries, stats.bytes), (2, 8));
+
+        // "b" was least recently used and evicted, "a" is still there
+        cache.get_or_load("a", now, load(4));
+        assert_eq!(cache.stats().hits, 2);
+        cache.get_or_load("a", SystemTime::UNIX_EPOCH, load(4));
+        assert_eq!(cache.stats().misses, 5);
+    }
+}
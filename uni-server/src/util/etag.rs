@@ -1,34 +1,256 @@
+use std::{
+    fs::Metadata,
+    io::{self, Read},
+    time::{Duration, SystemTime},
+};
+
 use axum::{
     http::{
         HeaderMap, StatusCode,
-        header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH},
+        header::{CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
     },
     response::{IntoResponse, Response},
 };
-use xxhash_rust::xxh3::xxh3_64;
+use xxhash_rust::xxh3::{Xxh3, xxh3_64};
 
-use crate::constants::CACHE_HEADER;
+use super::mfs::FileMeta;
 
 pub fn etag_hash(content: &[u8]) -> String {
     format!("\"{}\"", xxh3_64(content))
 }
 
-pub fn etag_check(content: &[u8], headers: &HeaderMap) -> Option<Response> {
-    let etag_val = etag_hash(content);
-
-    if let Some(if_none_match) = headers.get(IF_NONE_MATCH) {
-        if let Ok(cli_tag) = if_none_match.to_str() {
-            if cli_tag == etag_val {
-                return Some(
-                    (
-                        StatusCode::NOT_MODIFIED,
-                        [(CACHE_CONTROL, CACHE_HEADER), (ETAG, etag_val.as_str())],
-                    )
-                        .into_response(),
-                );
-            }
+/// Derive an ETag from file metadata (size, mtime and inode on unix),
+/// without touching the file content.
+pub fn etag_meta(metadata: &Metadata) -> String {
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+    #[cfg(not(unix))]
+    let inode = 0u64;
+
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    etag_stat(metadata.len(), modified, inode)
+}
+
+/// Derive an ETag from stored stat values, see [etag_meta]
+pub fn etag_stat(size: u64, modified: SystemTime, inode: u64) -> String {
+    let nanos = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+
+    let mut buf = Vec::with_capacity(40);
+    buf.extend_from_slice(&size.to_le_bytes());
+    buf.extend_from_slice(&nanos.to_le_bytes());
+    buf.extend_from_slice(&inode.to_le_bytes());
+    format!("\"{:x}-{:x}\"", size, xxh3_64(&buf))
+}
+
+/// Hash of the content read from `reader`, streamed so large files
+/// are never held in memory
+pub fn content_hash(mut reader: impl Read) -> io::Result<u64> {
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(hasher.digest()),
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Strong ETag of content hashed by [content_hash]
+pub fn etag_content(size: u64, hash: u64) -> String {
+    format!("\"{size:x}-{hash:016x}\"")
+}
+
+/// Format a time as an HTTP-date, used for `Last-Modified`
+pub fn http_date(time: SystemTime) -> String {
+    httpdate::fmt_http_date(time)
+}
+
+/// Entity-tag as defined in RFC 9110 section 8.8.3,
+/// `opaque` keeps the surrounding quotes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntityTag<'a> {
+    pub weak: bool,
+    pub opaque: &'a str,
+}
+
+impl<'a> EntityTag<'a> {
+    /// Parse a single entity-tag, e.g. `"abc"` or `W/"abc"`
+    pub fn parse(value: &'a str) -> Option<Self> {
+        match parse_etag_list(value)?.as_slice() {
+            [tag] => Some(*tag),
+            _ => None,
         }
     }
 
+    /// Strong comparison, both must be strong and identical
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.opaque == other.opaque
+    }
+
+    /// Weak comparison, opaque tags must be identical
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.opaque == other.opaque
+    }
+}
+
+/// Parse a comma separated list of entity-tags,
+/// returns [None] if any member is malformed.
+///
+/// Commas are valid inside an opaque tag, so the list is
+/// split by scanning quotes rather than by `,`.
+pub fn parse_etag_list(value: &str) -> Option<Vec<EntityTag<'_>>> {
+    const SEP: [char; 3] = [',', ' ', '\t'];
+
+    let mut tags = Vec::new();
+    let mut rest = value.trim_start_matches(SEP);
+    while !rest.is_empty() {
+        let (weak, tail) = match rest.strip_prefix("W/") {
+            Some(tail) => (true, tail),
+            None => (false, rest),
+        };
+        if !tail.starts_with('"') {
+            return None;
+        }
+        let end = tail[1..].find('"')? + 2;
+        tags.push(EntityTag {
+            weak,
+            opaque: &tail[..end],
+        });
+
+        rest = tail[end..].trim_start_matches([' ', '\t']);
+        if !rest.is_empty() && !rest.starts_with(',') {
+            return None;
+        }
+        rest = rest.trim_start_matches(SEP);
+    }
+
+    Some(tags)
+}
+
+/// Evaluate `If-None-Match` against the current ETag using weak comparison.
+///
+/// Handles `*`, lists and multiple header lines,
+/// malformed values never match.
+pub fn none_match_hit(etag_val: &str, headers: &HeaderMap) -> bool {
+    let Some(current) = EntityTag::parse(etag_val) else {
+        return false;
+    };
+
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| {
+            value.trim() == "*"
+                || parse_etag_list(value)
+                    .is_some_and(|tags| tags.iter().any(|tag| tag.weak_eq(&current)))
+        })
+}
+
+/// Evaluate `If-None-Match` and `If-Modified-Since` against the stored
+/// validators, returns the `304` response if the client copy is fresh.
+///
+/// As per RFC 9110, `If-Modified-Since` is only considered
+/// when `If-None-Match` is absent.
+pub fn conditional_check(
+    meta: &FileMeta,
+    cache_control: &str,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    let not_modified = if headers.contains_key(IF_NONE_MATCH) {
+        none_match_hit(&meta.etag, headers)
+    } else if let Some(if_modified_since) = headers.get(IF_MODIFIED_SINCE) {
+        if_modified_since
+            .to_str()
+            .ok()
+            .and_then(|date| httpdate::parse_http_date(date).ok())
+            .is_some_and(|since| truncate_secs(meta.modified) <= since)
+    } else {
+        false
+    };
+
+    if not_modified {
+        return Some(
+            (
+                StatusCode::NOT_MODIFIED,
+                [
+                    (CACHE_CONTROL, cache_control),
+                    (ETAG, meta.etag.as_str()),
+                    (LAST_MODIFIED, http_date(meta.modified).as_str()),
+                ],
+            )
+                .into_response(),
+        );
+    }
+
     None
 }
+
+/// Check an ETag without other validators against `If-None-Match`,
+/// used by embedded resources, returns the `304` response if matched.
+pub fn etag_match(etag_val: &str, cache_control: &str, headers: &HeaderMap) -> Option<Response> {
+    if none_match_hit(etag_val, headers) {
+        return Some(
+            (
+                StatusCode::NOT_MODIFIED,
+                [(CACHE_CONTROL, cache_control), (ETAG, etag_val)],
+            )
+                .into_response(),
+        );
+    }
+
+    None
+}
+
+/// HTTP-dates only carry whole seconds
+pub fn truncate_secs(time: SystemTime) -> SystemTime {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| SystemTime::UNIX_EPOCH + Duration::from_secs(d.as_secs()))
+        .unwrap_or(time)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_etag_list() {
+        let tags = parse_etag_list(r#""a", W/"b" ,"c,d""#).unwrap();
+        assert_eq!(
+            tags,
+            vec![
+                EntityTag {
+                    weak: false,
+                    opaque: r#""a""#
+                },
+                EntityTag {
+                    weak: true,
+                    opaque: r#""b""#
+                },
+                EntityTag {
+                    weak: false,
+                    opaque: r#""c,d""#
+                },
+            ]
+        );
+        assert!(parse_etag_list("abc").is_none());
+        assert!(parse_etag_list(r#""a" "b""#).is_none());
+        assert!(parse_etag_list(r#""unclosed"#).is_none());
+    }
+
+    #[test]
+    fn test_none_match_hit() {
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, r#""x", W/"abc""#.parse().unwrap());
+        assert!(none_match_hit(r#""abc""#, &headers));
+        assert!(!none_match_hit(r#""abd""#, &headers));
+
+        headers.insert(IF_NONE_MATCH, "*".parse().unwrap());
+        assert!(none_match_hit(r#""abd""#, &headers));
+    }
+}
@@ -1,33 +1,58 @@
+use std::{ops::Deref, sync::Arc};
+
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
 
-use crate::element::{sc::SugarCubeInfo, LoadedType};
+use crate::element::{LoadedType, sc::SugarCubeInfo};
 
 use super::AppState;
 
+/// Snapshot of a loaded SugarCube manage, stays valid while it is reloaded
+pub struct ScInfo(Arc<LoadedType>);
+
+impl ScInfo {
+    pub fn loaded(&self) -> &LoadedType {
+        &self.0
+    }
+}
+
+impl Deref for ScInfo {
+    type Target = SugarCubeInfo;
+
+    fn deref(&self) -> &SugarCubeInfo {
+        match &*self.0 {
+            LoadedType::SugarCube { info, .. } => info,
+            LoadedType::Plain { .. } => unreachable!("checked on extraction"),
+        }
+    }
+}
+
 pub trait ExtractInfo {
-    fn extract_info(&self, id: &str) -> Result<&LoadedType, Response>;
-    fn extract_sc_info(&self, id: &str) -> Result<&SugarCubeInfo, Response>;
+    #[allow(clippy::result_large_err)]
+    fn extract_info(&self, id: &str) -> Result<Arc<LoadedType>, Response>;
+    #[allow(clippy::result_large_err)]
+    fn extract_sc_info(&self, id: &str) -> Result<ScInfo, Response>;
 }
 
 impl ExtractInfo for AppState {
-    fn extract_info(&self, id: &str) -> Result<&LoadedType, Response> {
+    fn extract_info(&self, id: &str) -> Result<Arc<LoadedType>, Response> {
         self.get(id).ok_or_else(|| {
             (StatusCode::NOT_FOUND, format!("Info ID {id} not found")).into_response()
         })
     }
-    fn extract_sc_info(&self, id: &str) -> Result<&SugarCubeInfo, Response> {
-        match self.get(id).ok_or_else(|| {
+    fn extract_sc_info(&self, id: &str) -> Result<ScInfo, Response> {
+        let loaded = self.get(id).ok_or_else(|| {
             (StatusCode::NOT_FOUND, format!("SC Info ID {id} not found")).into_response()
-        })? {
+        })?;
+        match &*loaded {
             LoadedType::Plain { .. } => Err((
                 StatusCode::NOT_FOUND,
                 format!("Info Id {id} found, but not SC type!"),
             )
                 .into_response()),
-            LoadedType::SugarCube { info, .. } => Ok(info),
+            LoadedType::SugarCube { .. } => Ok(ScInfo(loaded)),
         }
     }
 }
@@ -1,22 +1,184 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use axum::body::Bytes;
+use flate2::read::DeflateDecoder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use percent_encoding::percent_decode_str;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::SystemTime,
+};
 use tracing::warn;
+use unicode_normalization::{UnicodeNormalization, is_nfc};
 use walkdir::WalkDir;
+use zip::{CompressionMethod, ZipArchive, read::ZipFile};
+
+use super::{
+    config::LookupConfig,
+    content_cache::ContentCache,
+    etag::{content_hash, etag_content, etag_meta},
+};
 
 /// File Type
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FileNode {
-    File(String),
+    File {
+        path: String,
+        meta: FileMeta,
+    },
+    /// Entry of a zip archive, read at its data offset
+    /// without parsing the archive again
+    ZipEntry {
+        archive: String,
+        entry: String,
+        location: ZipLocation,
+        meta: FileMeta,
+    },
+}
+
+/// Where the data of a zip entry lives inside the archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZipLocation {
+    pub data_start: u64,
+    pub compressed_size: u64,
+    pub method: ZipMethod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ZipMethod {
+    Stored,
+    Deflated,
+    /// Any other method, read through the archive reader
+    Other,
+}
+
+/// Metadata captured while scanning, so conditional requests
+/// can be answered without reading the file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMeta {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub etag: String,
+    /// Content hash, only computed for layer files
+    pub hash: Option<u64>,
+}
+
+impl FileMeta {
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        Self {
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            etag: etag_meta(metadata),
+            hash: None,
+        }
+    }
+
+    /// Meta with a strong ETag derived from the content hash
+    fn with_hash(size: u64, modified: SystemTime, hash: u64) -> Self {
+        Self {
+            size,
+            modified,
+            etag: etag_content(size, hash),
+            hash: Some(hash),
+        }
+    }
 }
 
 impl FileNode {
+    /// Node for a file outside of any layer, [None] if it is not a readable file
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+        Some(FileNode::File {
+            path: path.to_string_lossy().to_string(),
+            meta: FileMeta::from_metadata(&metadata),
+        })
+    }
+
+    pub fn meta(&self) -> &FileMeta {
+        match self {
+            FileNode::File { meta, .. } | FileNode::ZipEntry { meta, .. } => meta,
+        }
+    }
+
+    /// Key identifying the resolved file in the [ContentCache]
+    pub fn cache_key(&self) -> Cow<'_, str> {
+        match self {
+            FileNode::File { path, .. } => Cow::Borrowed(path),
+            FileNode::ZipEntry { archive, entry, .. } => Cow::Owned(format!("{archive}!/{entry}")),
+        }
+    }
+
+    /// Modification time on disk right now, which may differ from [FileMeta],
+    /// zip entries report the time of their archive
+    pub fn current_modified(&self) -> Option<SystemTime> {
+        match self {
+            FileNode::File { path, .. } | FileNode::ZipEntry { archive: path, .. } => {
+                fs::metadata(path).and_then(|m| m.modified()).ok()
+            }
+        }
+    }
+
+    /// The node as it is on disk right now, itself while unchanged since scanning,
+    /// otherwise with stat based meta for a file and looked up again for a zip
+    /// entry, whose offsets only hold for the scanned archive. [None] if it vanished
+    pub fn current(&self) -> Option<Cow<'_, FileNode>> {
+        match self {
+            FileNode::File { path, meta } => {
+                let current = FileMeta::from_metadata(&fs::metadata(path).ok()?);
+                if current.size == meta.size && current.modified == meta.modified {
+                    Some(Cow::Borrowed(self))
+                } else {
+                    Some(Cow::Owned(FileNode::File {
+                        path: path.clone(),
+                        meta: current,
+                    }))
+                }
+            }
+            FileNode::ZipEntry {
+                archive,
+                entry,
+                meta,
+                ..
+            } => {
+                let modified = fs::metadata(archive)
+                    .ok()?
+                    .modified()
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                if modified == meta.modified {
+                    return Some(Cow::Borrowed(self));
+                }
+                match lookup_zip_entry(archive, entry, modified) {
+                    Ok(node) => Some(Cow::Owned(node)),
+                    Err(err) => {
+                        warn!(
+                            "Entry '{}' of changed zip {} is unreadable: {}",
+                            entry, archive, err
+                        );
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read the content through the [ContentCache]
+    pub fn load(&self, cache: &ContentCache) -> Option<Bytes> {
+        let modified = self.current_modified()?;
+        cache.get_or_load(&self.cache_key(), modified, || {
+            self.resolve().map(|(data, _)| Bytes::from(data))
+        })
+    }
+
     pub fn resolve(&self) -> Option<(Vec<u8>, String)> {
         match self {
-            FileNode::File(path) => {
+            FileNode::File { path, .. } => {
                 let path = Path::new(path);
                 if path.exists() && path.is_file() {
-                    match fs::read(&path) {
+                    match fs::read(path) {
                         Ok(data) => Some((
                             data,
                             path.file_name()
@@ -34,6 +196,173 @@ impl FileNode {
                     None
                 }
             }
+            FileNode::ZipEntry {
+                archive,
+                entry,
+                location,
+                meta,
+            } => match read_zip_entry(archive, entry, location, meta.size) {
+                Ok(data) => Some((
+                    data,
+                    entry.rsplit('/').next().unwrap_or_default().to_string(),
+                )),
+                Err(err) => {
+                    warn!(
+                        "Failed to read entry '{}' of zip {}: {}",
+                        entry, archive, err
+                    );
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// Find an entry in an archive that changed since scanning, tagged by its
+/// CRC-32 so it is not hashed on every request until the layer is reloaded
+fn lookup_zip_entry(archive: &str, entry: &str, modified: SystemTime) -> Result<FileNode> {
+    let mut zip = ZipArchive::new(fs::File::open(archive)?)?;
+    let index = zip
+        .index_for_name(entry)
+        .or_else(|| zip.index_for_name(&format!("/{entry}")))
+        .ok_or_else(|| anyhow!("no longer in the archive"))?;
+    let zip_file = zip.by_index_raw(index)?;
+    let location = zip_location(&zip_file).ok_or_else(|| anyhow!("no data offset"))?;
+    let size = zip_file.size();
+    Ok(FileNode::ZipEntry {
+        archive: archive.to_string(),
+        entry: entry.to_string(),
+        location,
+        meta: FileMeta {
+            size,
+            modified,
+            etag: etag_content(size, zip_file.crc32() as u64),
+            hash: None,
+        },
+    })
+}
+
+fn zip_location<R: Read>(zip_file: &ZipFile<'_, R>) -> Option<ZipLocation> {
+    Some(ZipLocation {
+        data_start: zip_file.data_start()?,
+        compressed_size: zip_file.compressed_size(),
+        method: match zip_file.compression() {
+            CompressionMethod::Stored => ZipMethod::Stored,
+            CompressionMethod::Deflated => ZipMethod::Deflated,
+            _ => ZipMethod::Other,
+        },
+    })
+}
+
+fn read_zip_entry(
+    archive: &str,
+    entry: &str,
+    location: &ZipLocation,
+    size: u64,
+) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(archive)?;
+    if location.method == ZipMethod::Other {
+        let mut zip = ZipArchive::new(file)?;
+        let mut zip_file = zip.by_name(entry)?;
+        let mut data = Vec::with_capacity(size as usize);
+        zip_file.read_to_end(&mut data)?;
+        return Ok(data);
+    }
+
+    file.seek(SeekFrom::Start(location.data_start))?;
+    let mut raw = vec![0; location.compressed_size as usize];
+    file.read_exact(&mut raw)?;
+
+    match location.method {
+        ZipMethod::Deflated => {
+            let mut data = Vec::with_capacity(size as usize);
+            DeflateDecoder::new(raw.as_slice()).read_to_end(&mut data)?;
+            Ok(data)
+        }
+        _ => Ok(raw),
+    }
+}
+
+/// Prefix of overlay-style whiteout markers, `.wh.<name>` hides `<name>` of lower layers
+const WHITEOUT_PREFIX: &str = ".wh.";
+/// Marker hiding every lower entry under its directory
+const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+/// Optional manifest at the layer root
+const LAYER_MANIFEST: &str = "layer.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LayerManifest {
+    /// Paths or glob patterns to remove from lower layers
+    remove: Vec<String>,
+}
+
+/// Node of the virtual file tree of a [MapFileSystem]
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum TreeNode {
+    /// Size and file count include every descendant
+    Dir {
+        name: String,
+        path: String,
+        size: u64,
+        files: usize,
+        children: Vec<TreeNode>,
+    },
+    File {
+        name: String,
+        path: String,
+        size: u64,
+        layer: Option<String>,
+        mime: String,
+    },
+}
+
+/// Normalize a path for lookup, in the order percent-decoding, NFC, lowercase
+fn normalize_path<'a>(path: &'a str, lookup: &LookupConfig) -> Cow<'a, str> {
+    let mut path = Cow::Borrowed(path);
+    if lookup.percent_decode && path.contains('%') {
+        path = Cow::Owned(percent_decode_str(&path).decode_utf8_lossy().into_owned());
+    }
+    if lookup.unicode_nfc && !is_nfc(&path) {
+        path = Cow::Owned(path.nfc().collect());
+    }
+    if lookup.case_insensitive && path.chars().any(char::is_uppercase) {
+        path = Cow::Owned(path.to_lowercase());
+    }
+    path
+}
+
+/// Path hidden by a whiteout marker, empty for an opaque marker at the root
+fn whiteout_target(path: &str) -> Option<String> {
+    let (parent, name) = match path.rsplit_once('/') {
+        Some((parent, name)) => (Some(parent), name),
+        None => (None, path),
+    };
+    let target = if name == WHITEOUT_OPAQUE {
+        String::new()
+    } else {
+        name.strip_prefix(WHITEOUT_PREFIX)
+            .filter(|name| !name.is_empty())?
+            .to_string()
+    };
+    Some(match (parent, target.is_empty()) {
+        (Some(parent), true) => parent.to_string(),
+        (Some(parent), false) => format!("{parent}/{target}"),
+        (None, _) => target,
+    })
+}
+
+fn parse_manifest(content: &str, source: &Path) -> Vec<String> {
+    match toml::from_str::<LayerManifest>(content) {
+        Ok(manifest) => manifest
+            .remove
+            .into_iter()
+            .map(|path| path.trim_matches('/').to_string())
+            .collect(),
+        Err(err) => {
+            warn!("Invalid layer manifest in {:?}: {}", source, err);
+            Vec::new()
         }
     }
 }
@@ -41,23 +370,44 @@ impl FileNode {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MapFileSystem {
     map: HashMap<String, FileNode>,
+    /// Paths or patterns removed from lower layers when merged
+    whiteouts: Vec<String>,
+    /// Layer each entry comes from, only set on merged filesystems
+    #[serde(skip)]
+    origins: HashMap<String, String>,
+    /// Normalized path to entry, see [MapFileSystem::with_lookup]
+    #[serde(skip)]
+    aliases: HashMap<String, String>,
+    #[serde(skip)]
+    lookup: LookupConfig,
 }
 
 impl MapFileSystem {
-    pub fn new(map: HashMap<String, FileNode>) -> Self {
-        Self { map }
+    fn from_parts(map: HashMap<String, FileNode>, whiteouts: Vec<String>) -> Self {
+        Self {
+            map,
+            whiteouts,
+            origins: HashMap::new(),
+            aliases: HashMap::new(),
+            lookup: LookupConfig::default(),
+        }
     }
 
-    pub fn new_dir(source: impl AsRef<Path>) -> Result<Self> {
+    /// Scan a directory, hashing the content of every file.
+    ///
+    /// Hashes are taken from `previous` for files whose size and
+    /// modification time did not change.
+    pub fn new_dir(source: impl AsRef<Path>, previous: Option<&MapFileSystem>) -> Result<Self> {
         let source_path = source.as_ref();
         let mut map = HashMap::new();
+        let mut whiteouts = Vec::new();
 
         if !source_path.exists() || !source_path.is_dir() {
             warn!(
                 "Source dir not exists, or not a valid directory: {:?}",
                 source_path
             );
-            return Ok(Self { map });
+            return Ok(Self::from_parts(map, whiteouts));
         }
 
         for entry in WalkDir::new(source_path).into_iter().filter_map(Result::ok) {
@@ -67,6 +417,14 @@ impl MapFileSystem {
                 continue;
             }
 
+            let metadata = match fs::metadata(entry_path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    warn!("Failed to read metadata of {:?}: {}", entry_path, err);
+                    continue;
+                }
+            };
+
             let rel_path = entry_path.strip_prefix(source_path)?;
 
             let mut path_str = String::new();
@@ -77,20 +435,500 @@ impl MapFileSystem {
                 path_str.push_str(component.as_os_str().to_string_lossy().as_ref());
             }
 
+            if path_str == LAYER_MANIFEST {
+                match fs::read_to_string(entry_path) {
+                    Ok(content) => whiteouts.extend(parse_manifest(&content, entry_path)),
+                    Err(err) => warn!("Failed to read layer manifest {:?}: {}", entry_path, err),
+                }
+                continue;
+            }
+            if let Some(whiteout) = whiteout_target(&path_str) {
+                whiteouts.push(whiteout);
+                continue;
+            }
+
+            let size = metadata.len();
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            let reused = previous
+                .and_then(|prev| prev.map.get(&path_str))
+                .map(FileNode::meta)
+                .filter(|meta| meta.size == size && meta.modified == modified)
+                .and_then(|meta| meta.hash);
+            let hash = match reused {
+                Some(hash) => hash,
+                None => match fs::File::open(entry_path).and_then(content_hash) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        warn!("Failed to hash {:?}, skipping: {}", entry_path, err);
+                        continue;
+                    }
+                },
+            };
+
             map.insert(
                 path_str,
-                FileNode::File(entry_path.to_string_lossy().to_string()),
+                FileNode::File {
+                    path: entry_path.to_string_lossy().to_string(),
+                    meta: FileMeta::with_hash(size, modified, hash),
+                },
+            );
+        }
+
+        Ok(Self::from_parts(map, whiteouts))
+    }
+
+    /// Build from the entries of a zip archive, directories are skipped
+    pub fn new_zip(source: impl AsRef<Path>) -> Result<Self> {
+        let source_path = source.as_ref();
+        let archive = source_path.to_string_lossy().to_string();
+        let metadata = fs::metadata(source_path)?;
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+
+        let mut zip = ZipArchive::new(fs::File::open(source_path)?)?;
+        let mut map = HashMap::new();
+        let mut whiteouts = Vec::new();
+
+        if let Ok(mut manifest) = zip.by_name(LAYER_MANIFEST) {
+            let mut content = String::new();
+            match manifest.read_to_string(&mut content) {
+                Ok(_) => whiteouts.extend(parse_manifest(&content, source_path)),
+                Err(err) => warn!(
+                    "Failed to read layer manifest in {:?}: {}",
+                    source_path, err
+                ),
+            }
+        }
+
+        for index in 0..zip.len() {
+            let zip_file = match zip.by_index_raw(index) {
+                Ok(zip_file) => zip_file,
+                Err(err) => {
+                    warn!(
+                        "Failed to read entry {} of zip {:?}: {}",
+                        index, source_path, err
+                    );
+                    continue;
+                }
+            };
+            if zip_file.is_dir() {
+                continue;
+            }
+            let Some(location) = zip_location(&zip_file) else {
+                warn!(
+                    "Entry '{}' of zip {:?} has no data offset, skipping",
+                    zip_file.name(),
+                    source_path
+                );
+                continue;
+            };
+
+            let entry = zip_file.name().trim_start_matches('/').to_string();
+            if entry == LAYER_MANIFEST {
+                continue;
+            }
+            if let Some(whiteout) = whiteout_target(&entry) {
+                whiteouts.push(whiteout);
+                continue;
+            }
+            let size = zip_file.size();
+            drop(zip_file);
+            // Hashing the inflated content keeps the tag stable even if the
+            // archive is repacked, and equal to the same file in a dir layer
+            let hash = match zip.by_index(index).map(content_hash) {
+                Ok(Ok(hash)) => hash,
+                Ok(Err(err)) => {
+                    warn!(
+                        "Failed to hash '{}' of zip {:?}: {}",
+                        entry, source_path, err
+                    );
+                    continue;
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to read '{}' of zip {:?}: {}",
+                        entry, source_path, err
+                    );
+                    continue;
+                }
+            };
+            let meta = FileMeta::with_hash(size, modified, hash);
+
+            map.insert(
+                entry.clone(),
+                FileNode::ZipEntry {
+                    archive: archive.clone(),
+                    entry,
+                    location,
+                    meta,
+                },
             );
         }
 
-        Ok(Self { map })
+        Ok(Self::from_parts(map, whiteouts))
+    }
+
+    /// Merge named layers, later ones take precedence over earlier ones
+    /// and their whiteouts hide entries of the layers below.
+    pub fn merge<'a>(layers: impl IntoIterator<Item = (&'a str, &'a MapFileSystem)>) -> Self {
+        let mut map = HashMap::new();
+        let mut origins = HashMap::new();
+        for (name, layer) in layers {
+            if !layer.whiteouts.is_empty() {
+                let hides = layer.whiteout_matcher();
+                map.retain(|path: &String, _| !hides(path));
+                origins.retain(|path: &String, _| !hides(path));
+            }
+            for (path, node) in layer.iter() {
+                map.insert(path.clone(), node.clone());
+                origins.insert(path.clone(), name.to_string());
+            }
+        }
+        Self {
+            origins,
+            ..Self::from_parts(map, Vec::new())
+        }
+    }
+
+    /// Whether this layer hides `path` of lower layers
+    pub fn hides(&self, path: &str) -> bool {
+        self.whiteout_matcher()(path)
+    }
+
+    /// A whiteout hides the path itself and everything under it,
+    /// entries containing glob characters are matched as patterns.
+    fn whiteout_matcher(&self) -> impl Fn(&str) -> bool + '_ {
+        let mut globs = GlobSetBuilder::new();
+        let mut paths = Vec::new();
+        for whiteout in &self.whiteouts {
+            if whiteout.contains(['*', '?', '[', '{']) {
+                match Glob::new(whiteout) {
+                    Ok(glob) => {
+                        globs.add(glob);
+                    }
+                    Err(err) => warn!("Invalid whiteout pattern '{}': {}", whiteout, err),
+                }
+            } else {
+                paths.push(whiteout.as_str());
+            }
+        }
+        let globs = globs.build().unwrap_or_else(|_| GlobSet::empty());
+
+        move |path: &str| {
+            globs.is_match(path)
+                || paths.iter().any(|whiteout| {
+                    whiteout.is_empty()
+                        || path == *whiteout
+                        || (path.starts_with(whiteout)
+                            && path.as_bytes().get(whiteout.len()) == Some(&b'/'))
+                })
+        }
+    }
+
+    /// Name of the layer serving `path` in a merged filesystem
+    pub fn origin(&self, path: &str) -> Option<&str> {
+        self.origins.get(path).map(String::as_str)
+    }
+
+    /// Build the directory tree of the entries accepted by `filter`
+    pub fn tree(&self, filter: impl Fn(&str) -> bool) -> TreeNode {
+        #[derive(Default)]
+        struct DirBuilder<'a> {
+            dirs: BTreeMap<&'a str, DirBuilder<'a>>,
+            files: BTreeMap<&'a str, (&'a str, &'a FileNode)>,
+        }
+
+        fn build(name: &str, path: String, dir: DirBuilder, fs: &MapFileSystem) -> TreeNode {
+            let mut children = Vec::with_capacity(dir.dirs.len() + dir.files.len());
+            for (name, sub) in dir.dirs {
+                let sub_path = if path.is_empty() {
+                    name.to_string()
+                } else {
+                    format!("{path}/{name}")
+                };
+                children.push(build(name, sub_path, sub, fs));
+            }
+            for (name, (file_path, node)) in dir.files {
+                children.push(TreeNode::File {
+                    name: name.to_string(),
+                    path: file_path.to_string(),
+                    size: node.meta().size,
+                    layer: fs.origin(file_path).map(str::to_string),
+                    mime: mime_guess::from_path(file_path)
+                        .first_or_octet_stream()
+                        .to_string(),
+                });
+            }
+            let (size, files) = children
+                .iter()
+                .fold((0, 0), |(size, files), child| match child {
+                    TreeNode::Dir {
+                        size: s, files: f, ..
+                    } => (size + s, files + f),
+                    TreeNode::File { size: s, .. } => (size + s, files + 1),
+                });
+            TreeNode::Dir {
+                name: name.to_string(),
+                path,
+                size,
+                files,
+                children,
+            }
+        }
+
+        let mut root = DirBuilder::default();
+        for (path, node) in self.map.iter().filter(|(path, _)| filter(path)) {
+            let (parent, name) = match path.rsplit_once('/') {
+                Some((parent, name)) => (Some(parent), name),
+                None => (None, path.as_str()),
+            };
+            let mut dir = &mut root;
+            for part in parent.into_iter().flat_map(|parent| parent.split('/')) {
+                dir = dir.dirs.entry(part).or_default();
+            }
+            dir.files.insert(name, (path.as_str(), node));
+        }
+
+        build("", String::new(), root, self)
+    }
+
+    /// Enable normalized lookup, entries colliding under normalization
+    /// resolve to the smallest path in byte order and are logged.
+    pub fn with_lookup(mut self, lookup: &LookupConfig, label: &str) -> Self {
+        if !lookup.enabled() {
+            return self;
+        }
+
+        let mut groups: HashMap<String, Vec<&String>> = HashMap::new();
+        for path in self.map.keys() {
+            groups
+                .entry(normalize_path(path, lookup).into_owned())
+                .or_default()
+                .push(path);
+        }
+
+        let mut collisions = Vec::new();
+        let mut aliases = HashMap::with_capacity(groups.len());
+        for (normalized, mut paths) in groups {
+            paths.sort();
+            if paths.len() > 1 {
+                collisions.push(format!(
+                    "'{}' <- [{}]",
+                    paths[0],
+                    paths[1..]
+                        .iter()
+                        .map(|path| path.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            aliases.insert(normalized, paths[0].clone());
+        }
+        if !collisions.is_empty() {
+            collisions.sort();
+            warn!(
+                "{} paths of {} collide after normalization, resolved to the first of each: {}",
+                collisions.len(),
+                label,
+                collisions.join("; ")
+            );
+        }
+
+        self.aliases = aliases;
+        self.lookup = lookup.clone();
+        self
+    }
+
+    /// Actual entry path serving `path`, trying the normalized form when enabled
+    pub fn resolve_key<'a>(&'a self, path: &'a str) -> Option<&'a str> {
+        if let Some((key, _)) = self.map.get_key_value(path) {
+            return Some(key);
+        }
+        if !self.lookup.enabled() {
+            return None;
+        }
+        self.aliases
+            .get(normalize_path(path, &self.lookup).as_ref())
+            .map(String::as_str)
     }
 
     pub fn get(&self, path: &str) -> Option<&FileNode> {
-        self.map.get(path)
+        self.map
+            .get(path)
+            .or_else(|| self.map.get(self.resolve_key(path)?))
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (&String, &FileNode)> {
         self.map.iter()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_whiteouts() {
+        assert_eq!(
+            whiteout_target("img/.wh.a.png").as_deref(),
+            Some("img/a.png")
+        );
+        assert_eq!(whiteout_target("img/.wh..wh..opq").as_deref(), Some("img"));
+        assert_eq!(whiteout_target(".wh.").as_deref(), None);
+        assert_eq!(whiteout_target("img/a.png"), None);
+
+        let node = FileNode::File {
+            path: String::new(),
+            meta: FileMeta {
+                size: 0,
+                modified: SystemTime::UNIX_EPOCH,
+                etag: String::new(),
+                hash: None,
+            },
+        };
+        let lower = MapFileSystem::from_parts(
+            ["img/a.png", "img/b.png", "imgx/a.png", "js/app.js", "a.txt"]
+                .into_iter()
+                .map(|path| (path.to_string(), node.clone()))
+                .collect(),
+            Vec::new(),
+        );
+        let upper = MapFileSystem::from_parts(
+            HashMap::from([("img/b.png".to_string(), node.clone())]),
+            vec!["img".to_string(), "*.txt".to_string()],
+        );
+        let merged = MapFileSystem::merge([("lower", &lower), ("upper", &upper)]);
+
+        let mut left = merged
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["img/b.png", "imgx/a.png", "js/app.js"]);
+        assert_eq!(merged.origin("img/b.png"), Some("upper"));
+        assert_eq!(merged.origin("js/app.js"), Some("lower"));
+    }
+
+    #[test]
+    fn test_zip_layer() {
+        use std::io::Write;
+        use zip::{ZipWriter, write::SimpleFileOptions};
+
+        let path = std::env::temp_dir().join(format!("uni-zip-{}.zip", std::process::id()));
+        let stored = b"stored content".to_vec();
+        let deflated = "deflated content ".repeat(64).into_bytes();
+        let mut writer = ZipWriter::new(fs::File::create(&path).unwrap());
+        for (name, method, data) in [
+            ("a.txt", CompressionMethod::Stored, stored.as_slice()),
+            (
+                "js/app.js",
+                CompressionMethod::Deflated,
+                deflated.as_slice(),
+            ),
+            (
+                LAYER_MANIFEST,
+                CompressionMethod::Stored,
+                b"remove = [\"*.css\"]",
+            ),
+            ("img/.wh.a.png", CompressionMethod::Stored, b""),
+        ] {
+            let options = SimpleFileOptions::default().compression_method(method);
+            writer.start_file(name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let layer = MapFileSystem::new_zip(&path).unwrap();
+
+        let resolve = |key: &str| layer.map.get(key).and_then(FileNode::resolve).unwrap().0;
+        assert_eq!(resolve("a.txt"), stored);
+        assert_eq!(resolve("js/app.js"), deflated);
+        let scanned = &layer.map["a.txt"];
+        assert!(matches!(scanned.current(), Some(Cow::Borrowed(_))));
+
+        // Replaced on disk, the stored entry moves behind a new one
+        let mut writer = ZipWriter::new(fs::File::create(&path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("b.txt", options).unwrap();
+        writer
+            .write_all(b"pushes a.txt further into the archive")
+            .unwrap();
+        writer.start_file("a.txt", options).unwrap();
+        writer.write_all(b"replaced content").unwrap();
+        writer.finish().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(scanned.meta().modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        let current = scanned.current().unwrap();
+        assert_eq!(current.resolve().unwrap().0, b"replaced content");
+        assert_ne!(current.meta().etag, scanned.meta().etag);
+        assert!(layer.map["js/app.js"].current().is_none());
+        fs::remove_file(&path).ok();
+        assert!(matches!(
+            layer.map.get("js/app.js"),
+            Some(FileNode::ZipEntry {
+                location: ZipLocation {
+                    method: ZipMethod::Deflated,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert!(!layer.map.contains_key(LAYER_MANIFEST));
+        assert!(!layer.map.contains_key("img/.wh.a.png"));
+
+        let node = layer.map["a.txt"].clone();
+        let lower = MapFileSystem::from_parts(
+            ["img/a.png", "img/b.png", "css/site.css"]
+                .into_iter()
+                .map(|path| (path.to_string(), node.clone()))
+                .collect(),
+            Vec::new(),
+        );
+        let merged = MapFileSystem::merge([("lower", &lower), ("zip", &layer)]);
+        let mut left = merged
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["a.txt", "img/b.png", "js/app.js"]);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let node = FileNode::File {
+            path: String::new(),
+            meta: FileMeta {
+                size: 0,
+                modified: SystemTime::UNIX_EPOCH,
+                etag: String::new(),
+                hash: None,
+            },
+        };
+        let mfs = MapFileSystem::from_parts(
+            ["img/Cat.PNG", "img/cat.png", "img/Caf\u{e9}%20bar.png"]
+                .into_iter()
+                .map(|path| (path.to_string(), node.clone()))
+                .collect(),
+            Vec::new(),
+        );
+        assert_eq!(mfs.resolve_key("img/CAT.png"), None);
+
+        let mfs = mfs.with_lookup(
+            &LookupConfig {
+                case_insensitive: true,
+                unicode_nfc: true,
+                percent_decode: true,
+            },
+            "test",
+        );
+        assert_eq!(mfs.resolve_key("img/cat.png"), Some("img/cat.png"));
+        assert_eq!(mfs.resolve_key("img/CAT.png"), Some("img/Cat.PNG"));
+        assert_eq!(
+            mfs.resolve_key("img/cafe\u{301} BAR.png"),
+            Some("img/Caf\u{e9}%20bar.png")
+        );
+    }
+}
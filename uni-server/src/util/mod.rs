@@ -1,28 +1,81 @@
-use std::{env::current_dir, path::PathBuf};
+use anyhow::{Result, anyhow};
+use std::{
+    env::current_dir,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock},
+};
 use tracing::{error, error_span};
 
-use crate::element::{LoadedMapping, LoadedType};
+use crate::element::{LoadedMapping, LoadedType, load_manage};
+use config::{ReadConfig, config_ref};
+use content_cache::ContentCache;
 
+pub(crate) mod admin;
+pub(crate) mod bin_cache;
+pub(crate) mod cache_policy;
+pub(crate) mod compress;
 pub(crate) mod config;
+pub(crate) mod content_cache;
 pub(crate) mod etag;
 pub(crate) mod extract;
 pub(crate) mod mfs;
 pub(crate) mod path_ext;
+pub(crate) mod range;
 
 #[derive(Debug)]
-pub struct AppState(LoadedMapping);
+pub struct AppState {
+    /// Replaced per manage when it is reloaded, handlers keep the snapshot they got
+    mapping: RwLock<LoadedMapping>,
+    /// Serializes changes made through the management API
+    admin: Mutex<()>,
+    content_cache: ContentCache,
+}
 
 impl AppState {
     pub fn new(mapping: LoadedMapping) -> Self {
-        Self(mapping)
+        Self {
+            mapping: RwLock::new(mapping),
+            admin: Mutex::new(()),
+            content_cache: ContentCache::new(config_ref().content_cache()),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<LoadedType>> {
+        self.mapping
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(id)
+            .cloned()
+    }
+
+    pub fn iter(&self) -> Vec<(String, Arc<LoadedType>)> {
+        self.mapping
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(id, loaded)| (id.clone(), loaded.clone()))
+            .collect()
+    }
+
+    /// Held while changing files of a manage and reloading it
+    pub fn admin_lock(&self) -> MutexGuard<'_, ()> {
+        self.admin.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
-    pub fn get(&self, id: &str) -> Option<&LoadedType> {
-        self.0.get(id)
+    /// Load a manage again from disk and swap it in
+    pub fn reload(&self, id: &str) -> Result<Arc<LoadedType>> {
+        let current = self
+            .get(id)
+            .ok_or_else(|| anyhow!("Manage {id} not found"))?;
+        let loaded = load_manage(id, current.original_ref())?;
+
+        let mut mapping = self.mapping.write().unwrap_or_else(PoisonError::into_inner);
+        mapping.insert(id.to_string(), loaded);
+        Ok(mapping.get(id).cloned().expect("just inserted"))
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&String, &LoadedType)> {
-        self.0.iter()
+    pub fn content_cache(&self) -> &ContentCache {
+        &self.content_cache
     }
 }
 
@@ -39,12 +92,12 @@ pub fn cd() -> PathBuf {
 
     #[cfg(debug_assertions)]
     {
-        return base.join(".run");
+        base.join(".run")
     }
 
     #[cfg(not(debug_assertions))]
     {
-        return base;
+        base
     }
 }
 
@@ -16,6 +16,6 @@ impl PathHelper for Path {
     fn extension_eq(&self, ext: &str) -> bool {
         self.extension()
             .and_then(|e| e.to_str())
-            .map_or(false, |e| e.eq_ignore_ascii_case(ext))
+            .is_some_and(|e| e.eq_ignore_ascii_case(ext))
     }
 }
@@ -0,0 +1,369 @@
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use axum::{
+    body::Bytes,
+    http::{
+        HeaderMap, StatusCode,
+        header::{
+            ACCEPT_RANGES, CACHE_CONTROL, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE,
+            LAST_MODIFIED, RANGE,
+        },
+    },
+    response::{IntoResponse, Response},
+};
+use tracing::error;
+use xxhash_rust::xxh3::xxh3_64;
+
+use super::{
+    etag::{EntityTag, http_date, truncate_secs},
+    mfs::FileMeta,
+};
+
+/// Upper bound of ranges accepted in a single request,
+/// anything above is answered with the full body.
+const MAX_RANGES: usize = 16;
+
+/// Parsed result of a `Range` header against a known length.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeSpec {
+    /// No usable `Range` header, serve the full body.
+    Full,
+    /// One or more satisfiable ranges, sorted and coalesced.
+    Partial(Vec<Range<u64>>),
+    /// Syntactically valid but nothing overlaps the body.
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header value (`bytes=0-99, 200-, -50`).
+///
+/// Unknown units and malformed values are ignored as per RFC 9110,
+/// which means the full body should be served.
+pub fn parse_range(value: &str, len: u64) -> RangeSpec {
+    let Some(specs) = value.trim().strip_prefix("bytes=") else {
+        return RangeSpec::Full;
+    };
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        count += 1;
+        if count > MAX_RANGES {
+            return RangeSpec::Full;
+        }
+
+        let Some((first, last)) = spec.split_once('-') else {
+            return RangeSpec::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+
+        let range = if first.is_empty() {
+            // Suffix range, last N bytes
+            let Ok(suffix) = last.parse::<u64>() else {
+                return RangeSpec::Full;
+            };
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            len.saturating_sub(suffix)..len
+        } else {
+            let Ok(first) = first.parse::<u64>() else {
+                return RangeSpec::Full;
+            };
+            let last = if last.is_empty() {
+                None
+            } else {
+                match last.parse::<u64>() {
+                    Ok(last) if last >= first => Some(last),
+                    _ => return RangeSpec::Full,
+                }
+            };
+            if first >= len {
+                continue;
+            }
+            first..last.map_or(len, |last| last.saturating_add(1).min(len))
+        };
+        ranges.push(range);
+    }
+
+    if count == 0 {
+        return RangeSpec::Full;
+    }
+    if ranges.is_empty() {
+        return RangeSpec::Unsatisfiable;
+    }
+
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(prev) if range.start <= prev.end => prev.end = prev.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    RangeSpec::Partial(merged)
+}
+
+/// Check whether `If-Range` allows the `Range` header to be honored.
+///
+/// Entity-tags use strong comparison, so weak tags never match,
+/// dates must equal the `Last-Modified` of the file.
+pub fn if_range_matches(headers: &HeaderMap, meta: &FileMeta) -> bool {
+    match headers.get(IF_RANGE).map(|v| v.to_str()) {
+        None => true,
+        Some(Ok(value)) if is_entity_tag(value) => {
+            match (EntityTag::parse(value), EntityTag::parse(&meta.etag)) {
+                (Some(tag), Some(current)) => tag.strong_eq(&current),
+                _ => false,
+            }
+        }
+        Some(Ok(value)) => {
+            httpdate::parse_http_date(value).is_ok_and(|date| date == truncate_secs(meta.modified))
+        }
+        Some(Err(_)) => false,
+    }
+}
+
+/// Only `"` and `W/"` start an entity-tag, HTTP-dates may start with `W` as in `Wed,`
+fn is_entity_tag(value: &str) -> bool {
+    let value = value.trim_start();
+    value.starts_with('"') || value.starts_with("W/\"")
+}
+
+/// The ranges a request asks for, [RangeSpec::Full] without a usable `Range`
+/// header or when `If-Range` does not match.
+pub fn requested_range(headers: &HeaderMap, meta: &FileMeta, len: u64) -> RangeSpec {
+    match headers.get(RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) if if_range_matches(headers, meta) => parse_range(value, len),
+        _ => RangeSpec::Full,
+    }
+}
+
+/// Build a response for the given body, honoring `Range` and `If-Range`.
+///
+/// Responds `200` with the full body, `206` with one or more parts
+/// (`multipart/byteranges` for more than one), or `416` when not satisfiable.
+pub fn range_response(
+    content: Bytes,
+    content_type: &str,
+    meta: &FileMeta,
+    cache_control: &str,
+    headers: &HeaderMap,
+) -> Response {
+    let len = content.len() as u64;
+    match requested_range(headers, meta, len) {
+        RangeSpec::Full => (
+            StatusCode::OK,
+            [
+                (CONTENT_TYPE, content_type),
+                (CACHE_CONTROL, cache_control),
+                (ETAG, meta.etag.as_str()),
+                (LAST_MODIFIED, http_date(meta.modified).as_str()),
+                (ACCEPT_RANGES, "bytes"),
+            ],
+            content,
+        )
+            .into_response(),
+        RangeSpec::Unsatisfiable => unsatisfiable_response(len),
+        RangeSpec::Partial(ranges) => {
+            partial_response(&ranges, len, content_type, meta, cache_control, |range| {
+                Ok(content.slice(range.start as usize..range.end as usize))
+            })
+        }
+    }
+}
+
+/// Answer a range request on a file by reading only the requested parts,
+/// so seeking in large media never loads all of it.
+///
+/// [None] when the request asks for the full body.
+pub fn file_range_response(
+    path: &Path,
+    content_type: &str,
+    meta: &FileMeta,
+    cache_control: &str,
+    headers: &HeaderMap,
+) -> Option<Response> {
+    match requested_range(headers, meta, meta.size) {
+        RangeSpec::Full => None,
+        RangeSpec::Unsatisfiable => Some(unsatisfiable_response(meta.size)),
+        RangeSpec::Partial(ranges) => {
+            let mut file = match fs::File::open(path) {
+                Ok(file) => file,
+                Err(err) => {
+                    error!("Failed to open {}: {err}", path.display());
+                    return Some(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                }
+            };
+            Some(partial_response(
+                &ranges,
+                meta.size,
+                content_type,
+                meta,
+                cache_control,
+                |range| {
+                    let mut part = vec![0; (range.end - range.start) as usize];
+                    file.seek(SeekFrom::Start(range.start))?;
+                    file.read_exact(&mut part)?;
+                    Ok(Bytes::from(part))
+                },
+            ))
+        }
+    }
+}
+
+fn unsatisfiable_response(len: u64) -> Response {
+    (
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        [
+            (CONTENT_RANGE, format!("bytes */{len}").as_str()),
+            (ACCEPT_RANGES, "bytes"),
+        ],
+    )
+        .into_response()
+}
+
+/// `206` with the parts produced by `read`, as `multipart/byteranges`
+/// for more than one range.
+fn partial_response(
+    ranges: &[Range<u64>],
+    len: u64,
+    content_type: &str,
+    meta: &FileMeta,
+    cache_control: &str,
+    mut read: impl FnMut(&Range<u64>) -> io::Result<Bytes>,
+) -> Response {
+    let last_modified = http_date(meta.modified);
+    let mut parts = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match read(range) {
+            Ok(part) => parts.push(part),
+            Err(err) => {
+                error!("Failed to read bytes {range:?} of a {len} byte body: {err}");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    if let [range] = ranges {
+        let part = parts.pop().expect("one part per range");
+        return (
+            StatusCode::PARTIAL_CONTENT,
+            [
+                (CONTENT_TYPE, content_type),
+                (CACHE_CONTROL, cache_control),
+                (ETAG, meta.etag.as_str()),
+                (LAST_MODIFIED, last_modified.as_str()),
+                (ACCEPT_RANGES, "bytes"),
+                (
+                    CONTENT_RANGE,
+                    &format!("bytes {}-{}/{len}", range.start, range.end - 1),
+                ),
+            ],
+            part,
+        )
+            .into_response();
+    }
+
+    let boundary = format!("{:016x}", xxh3_64(meta.etag.as_bytes()) ^ len);
+    let mut body = Vec::new();
+    for (range, part) in ranges.iter().zip(parts) {
+        body.extend_from_slice(
+            format!(
+                "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {}-{}/{len}\r\n\r\n",
+                range.start,
+                range.end - 1
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&part);
+    }
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+
+    let multipart = format!("multipart/byteranges; boundary={boundary}");
+    (
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (CONTENT_TYPE, multipart.as_str()),
+            (CACHE_CONTROL, cache_control),
+            (ETAG, meta.etag.as_str()),
+            (LAST_MODIFIED, last_modified.as_str()),
+            (ACCEPT_RANGES, "bytes"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(
+            parse_range("bytes=0-99", 1000),
+            RangeSpec::Partial(vec![Range { start: 0, end: 100 }])
+        );
+        assert_eq!(
+            parse_range("bytes=900-", 1000),
+            RangeSpec::Partial(vec![Range {
+                start: 900,
+                end: 1000
+            }])
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            RangeSpec::Partial(vec![Range {
+                start: 900,
+                end: 1000
+            }])
+        );
+        assert_eq!(
+            parse_range("bytes=0-5000", 1000),
+            RangeSpec::Partial(vec![Range {
+                start: 0,
+                end: 1000
+            }])
+        );
+        assert_eq!(
+            parse_range("bytes=0-9, 5-19, 50-59", 1000),
+            RangeSpec::Partial(vec![0..20, 50..60])
+        );
+        assert_eq!(parse_range("bytes=1000-", 1000), RangeSpec::Unsatisfiable);
+        assert_eq!(parse_range("bytes=9-0", 1000), RangeSpec::Full);
+        assert_eq!(parse_range("items=0-1", 1000), RangeSpec::Full);
+        assert_eq!(parse_range("bytes=abc", 1000), RangeSpec::Full);
+    }
+
+    #[test]
+    fn test_if_range() {
+        let modified = httpdate::parse_http_date("Wed, 21 Oct 2026 07:28:00 GMT").unwrap();
+        let meta = FileMeta {
+            size: 0,
+            modified,
+            etag: "\"abc\"".to_string(),
+            hash: None,
+        };
+        let if_range = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(IF_RANGE, value.parse().unwrap());
+            if_range_matches(&headers, &meta)
+        };
+        assert!(if_range("Wed, 21 Oct 2026 07:28:00 GMT"));
+        assert!(!if_range("Thu, 22 Oct 2026 07:28:00 GMT"));
+        assert!(if_range("\"abc\""));
+        assert!(!if_range("W/\"abc\""));
+        assert!(!if_range("\"def\""));
+        assert!(if_range_matches(&HeaderMap::new(), &meta));
+    }
+}